@@ -7,6 +7,15 @@ use std::{borrow::Cow, ffi::CStr};
 
 include!(concat!(env!("OUT_DIR"), "/sim86_shared.rs"));
 
+mod safe;
+mod table;
+
+pub use safe::{
+    decode, AddressTerm, EffectiveAddress, Immediate, Instruction, InstructionFlags, Operand,
+    Operation, Register,
+};
+pub use table::{encodings, BitsUsage, Encoding, Encodings, InstructionBits};
+
 pub fn get_version() -> u32 {
     unsafe { Sim86_GetVersion() }
 }
@@ -48,6 +57,18 @@ pub fn mnemonic_from_operation_type(op: operation_type) -> Cow<'static, str> {
     unsafe { CStr::from_ptr(Sim86_MnemonicFromOperationType(op)).to_string_lossy() }
 }
 
+pub fn register_name(access: &register_access) -> Cow<'static, str> {
+    // The reference only reads through the pointer, but wants it mutable
+    let mut access = *access;
+    unsafe { CStr::from_ptr(Sim86_RegisterNameFromOperand(&mut access)).to_string_lossy() }
+}
+
+/// Renders the terms and displacement as `bp+di-4`, or `segment:offset` when the address has
+/// an explicit segment. The brackets, size and segment override belong to the instruction.
+pub fn effective_address_to_string(address: &effective_address_expression) -> String {
+    EffectiveAddress::from(*address).to_string()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -57,4 +78,58 @@ mod tests {
         let version = get_version();
         assert_eq!(version, SIM86_VERSION);
     }
+
+    #[test]
+    fn register_names() {
+        let name = |Index, Offset, Count| {
+            register_name(&register_access {
+                Index,
+                Offset,
+                Count,
+            })
+        };
+        assert_eq!(name(1, 0, 2), "ax");
+        assert_eq!(name(1, 0, 1), "al");
+        assert_eq!(name(1, 1, 1), "ah");
+        assert_eq!(name(12, 0, 2), "ds");
+    }
+
+    #[test]
+    fn effective_addresses() {
+        let register = |Index| register_access {
+            Index,
+            Offset: 0,
+            Count: 2,
+        };
+        let term = |Index| effective_address_term {
+            Register: register(Index),
+            Scale: 1,
+        };
+        let address = |Terms, Displacement, Flags| effective_address_expression {
+            Terms,
+            ExplicitSegment: 123,
+            Displacement,
+            Flags,
+        };
+        assert_eq!(
+            effective_address_to_string(&address([term(6), term(8)], -4, 0)),
+            "bp+di-4"
+        );
+        assert_eq!(
+            effective_address_to_string(&address([term(2), term(0)], 0, 0)),
+            "bx"
+        );
+        assert_eq!(
+            effective_address_to_string(&address([term(0), term(0)], 456, 0)),
+            "456"
+        );
+        assert_eq!(
+            effective_address_to_string(&address(
+                [term(0), term(0)],
+                456,
+                effective_address_flag_Address_ExplicitSegment
+            )),
+            "123:456"
+        );
+    }
 }
@@ -29,7 +29,7 @@ fn main() {
 
     let table = get_8086_instruction_table();
     println!(
-        "8086 Instruction Instruction Encoding Count: {}",
+        "; 8086 Instruction Instruction Encoding Count: {}",
         table.EncodingCount
     );
 
@@ -50,20 +50,16 @@ fn main() {
 
     let buf = file_buf.unwrap_or_else(|| EXAMPLE_DISASSEMBLY.to_vec());
 
+    println!("bits 16");
+
     let mut offset = 0u32;
     while offset < buf.len() as u32 {
-        let decoded = decode_8086_instruction(&buf[offset as usize..]);
+        let decoded = decode(&buf[offset as usize..]);
         if let Some(decoded) = decoded {
-            if decoded.Op != operation_type_Op_None {
-                offset += decoded.Size;
-                let mnemonic = mnemonic_from_operation_type(decoded.Op);
-                println!(
-                    "size:{} op:{mnemonic} Flags:{:#0x}",
-                    decoded.Size, decoded.Flags
-                );
-            }
+            offset += decoded.size;
+            println!("{decoded}");
         } else {
-            println!("Unrecognised instruction");
+            println!("; Unrecognised instruction");
             break;
         }
     }
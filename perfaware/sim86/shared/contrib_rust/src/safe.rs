@@ -0,0 +1,452 @@
+//! A safe layer over the raw bindings. Decoded instructions are copied into plain Rust types,
+//! so the operand union is only ever read in one place, selected by its type tag.
+
+use std::borrow::Cow;
+use std::fmt;
+
+use bitflags::bitflags;
+
+use crate::*;
+
+macro_rules! operations {
+    ($($variant:ident = $raw:ident,)*) => {
+        /// Every operation the reference decoder can produce, minus `Op_None`.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #[repr(u32)]
+        pub enum Operation {
+            $($variant = $raw,)*
+        }
+
+        impl Operation {
+            pub const ALL: &'static [Operation] = &[$(Operation::$variant,)*];
+
+            pub fn from_raw(op: operation_type) -> Option<Operation> {
+                match op {
+                    $($raw => Some(Operation::$variant),)*
+                    _ => None,
+                }
+            }
+
+            pub fn raw(self) -> operation_type {
+                self as operation_type
+            }
+        }
+    };
+}
+
+operations! {
+    Mov = operation_type_Op_mov,
+    Push = operation_type_Op_push,
+    Pop = operation_type_Op_pop,
+    Xchg = operation_type_Op_xchg,
+    In = operation_type_Op_in,
+    Out = operation_type_Op_out,
+    Xlat = operation_type_Op_xlat,
+    Lea = operation_type_Op_lea,
+    Lds = operation_type_Op_lds,
+    Les = operation_type_Op_les,
+    Lahf = operation_type_Op_lahf,
+    Sahf = operation_type_Op_sahf,
+    Pushf = operation_type_Op_pushf,
+    Popf = operation_type_Op_popf,
+    Add = operation_type_Op_add,
+    Adc = operation_type_Op_adc,
+    Inc = operation_type_Op_inc,
+    Aaa = operation_type_Op_aaa,
+    Daa = operation_type_Op_daa,
+    Sub = operation_type_Op_sub,
+    Sbb = operation_type_Op_sbb,
+    Dec = operation_type_Op_dec,
+    Neg = operation_type_Op_neg,
+    Cmp = operation_type_Op_cmp,
+    Aas = operation_type_Op_aas,
+    Das = operation_type_Op_das,
+    Mul = operation_type_Op_mul,
+    Imul = operation_type_Op_imul,
+    Aam = operation_type_Op_aam,
+    Div = operation_type_Op_div,
+    Idiv = operation_type_Op_idiv,
+    Aad = operation_type_Op_aad,
+    Cbw = operation_type_Op_cbw,
+    Cwd = operation_type_Op_cwd,
+    Not = operation_type_Op_not,
+    Shl = operation_type_Op_shl,
+    Shr = operation_type_Op_shr,
+    Sar = operation_type_Op_sar,
+    Rol = operation_type_Op_rol,
+    Ror = operation_type_Op_ror,
+    Rcl = operation_type_Op_rcl,
+    Rcr = operation_type_Op_rcr,
+    And = operation_type_Op_and,
+    Test = operation_type_Op_test,
+    Or = operation_type_Op_or,
+    Xor = operation_type_Op_xor,
+    Rep = operation_type_Op_rep,
+    Movs = operation_type_Op_movs,
+    Cmps = operation_type_Op_cmps,
+    Scas = operation_type_Op_scas,
+    Lods = operation_type_Op_lods,
+    Stos = operation_type_Op_stos,
+    Call = operation_type_Op_call,
+    Jmp = operation_type_Op_jmp,
+    Ret = operation_type_Op_ret,
+    Retf = operation_type_Op_retf,
+    Je = operation_type_Op_je,
+    Jl = operation_type_Op_jl,
+    Jle = operation_type_Op_jle,
+    Jb = operation_type_Op_jb,
+    Jbe = operation_type_Op_jbe,
+    Jp = operation_type_Op_jp,
+    Jo = operation_type_Op_jo,
+    Js = operation_type_Op_js,
+    Jne = operation_type_Op_jne,
+    Jnl = operation_type_Op_jnl,
+    Jg = operation_type_Op_jg,
+    Jnb = operation_type_Op_jnb,
+    Ja = operation_type_Op_ja,
+    Jnp = operation_type_Op_jnp,
+    Jno = operation_type_Op_jno,
+    Jns = operation_type_Op_jns,
+    Loop = operation_type_Op_loop,
+    Loopz = operation_type_Op_loopz,
+    Loopnz = operation_type_Op_loopnz,
+    Jcxz = operation_type_Op_jcxz,
+    Int = operation_type_Op_int,
+    Int3 = operation_type_Op_int3,
+    Into = operation_type_Op_into,
+    Iret = operation_type_Op_iret,
+    Clc = operation_type_Op_clc,
+    Cmc = operation_type_Op_cmc,
+    Stc = operation_type_Op_stc,
+    Cld = operation_type_Op_cld,
+    Std = operation_type_Op_std,
+    Cli = operation_type_Op_cli,
+    Sti = operation_type_Op_sti,
+    Hlt = operation_type_Op_hlt,
+    Wait = operation_type_Op_wait,
+    Esc = operation_type_Op_esc,
+    Lock = operation_type_Op_lock,
+    Segment = operation_type_Op_segment,
+}
+
+impl Operation {
+    pub fn mnemonic(self) -> Cow<'static, str> {
+        mnemonic_from_operation_type(self.raw())
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.mnemonic())
+    }
+}
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct InstructionFlags: u32 {
+        const LOCK = instruction_flag_Inst_Lock;
+        const REP = instruction_flag_Inst_Rep;
+        const SEGMENT = instruction_flag_Inst_Segment;
+        const WIDE = instruction_flag_Inst_Wide;
+        const FAR = instruction_flag_Inst_Far;
+    }
+}
+
+/// A register, or part of one. `offset` and `count` are in bytes, so `ah` is offset 1,
+/// count 1 of register index 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Register {
+    pub index: u32,
+    pub offset: u32,
+    pub count: u32,
+}
+
+impl Register {
+    pub fn name(&self) -> Cow<'static, str> {
+        register_name(&(*self).into())
+    }
+}
+
+impl From<register_access> for Register {
+    fn from(access: register_access) -> Self {
+        Register {
+            index: access.Index,
+            offset: access.Offset,
+            count: access.Count,
+        }
+    }
+}
+
+impl From<Register> for register_access {
+    fn from(register: Register) -> Self {
+        register_access {
+            Index: register.index,
+            Offset: register.offset,
+            Count: register.count,
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AddressTerm {
+    pub register: Register,
+    pub scale: i32,
+}
+
+/// A memory operand. Unused terms are `None`; an explicit segment means a far address
+/// `segment:displacement` rather than a register expression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EffectiveAddress {
+    pub terms: [Option<AddressTerm>; 2],
+    pub explicit_segment: Option<u32>,
+    pub displacement: i32,
+}
+
+impl From<effective_address_expression> for EffectiveAddress {
+    fn from(address: effective_address_expression) -> Self {
+        let term = |term: &effective_address_term| {
+            (term.Register.Index != 0).then(|| AddressTerm {
+                register: term.Register.into(),
+                scale: term.Scale,
+            })
+        };
+        EffectiveAddress {
+            terms: [term(&address.Terms[0]), term(&address.Terms[1])],
+            explicit_segment: (address.Flags & effective_address_flag_Address_ExplicitSegment != 0)
+                .then_some(address.ExplicitSegment),
+            displacement: address.Displacement,
+        }
+    }
+}
+
+/// Prints the bracketed part of the operand, e.g. `bp+di-4`, or `segment:offset` for an
+/// explicit segment.
+impl fmt::Display for EffectiveAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(segment) = self.explicit_segment {
+            return write!(f, "{}:{}", segment, self.displacement);
+        }
+        let mut separator = "";
+        for term in self.terms.iter().flatten() {
+            f.write_str(separator)?;
+            if term.scale != 1 {
+                write!(f, "{}*", term.scale)?;
+            }
+            write!(f, "{}", term.register)?;
+            separator = "+";
+        }
+        // A direct address has no terms to be added to, so it is printed unsigned
+        if separator.is_empty() {
+            write!(f, "{}", self.displacement)?;
+        } else if self.displacement != 0 {
+            write!(f, "{:+}", self.displacement)?;
+        }
+        Ok(())
+    }
+}
+
+/// An immediate value. Relative jump displacements are relative to the end of the
+/// instruction, as encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Immediate {
+    pub value: i32,
+    pub relative: bool,
+}
+
+impl From<immediate> for Immediate {
+    fn from(immediate: immediate) -> Self {
+        Immediate {
+            value: immediate.Value,
+            relative: immediate.Flags & immediate_flag_Immediate_RelativeJumpDisplacement != 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operand {
+    Register(Register),
+    Memory(EffectiveAddress),
+    Immediate(Immediate),
+}
+
+impl Operand {
+    /// Reads the union member selected by the operand's type, or `None` for an unused slot.
+    pub fn from_raw(operand: &instruction_operand) -> Option<Operand> {
+        // SAFETY: the reference decoder only ever writes the member named by `Type`
+        unsafe {
+            match operand.Type {
+                operand_type_Operand_Register => {
+                    Some(Operand::Register(operand.__bindgen_anon_1.Register.into()))
+                }
+                operand_type_Operand_Memory => {
+                    Some(Operand::Memory(operand.__bindgen_anon_1.Address.into()))
+                }
+                operand_type_Operand_Immediate => Some(Operand::Immediate(
+                    operand.__bindgen_anon_1.Immediate.into(),
+                )),
+                _ => None,
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Instruction {
+    pub address: u32,
+    pub size: u32,
+    pub operation: Operation,
+    pub flags: InstructionFlags,
+    pub operands: [Option<Operand>; 2],
+    /// Only set when `flags` contains `SEGMENT`.
+    pub segment_override: Option<Register>,
+}
+
+impl Instruction {
+    /// `None` for `Op_None`, which is how the reference reports bytes it could not decode.
+    pub fn from_raw(decoded: &instruction) -> Option<Instruction> {
+        let flags = InstructionFlags::from_bits_truncate(decoded.Flags);
+        Some(Instruction {
+            address: decoded.Address,
+            size: decoded.Size,
+            operation: Operation::from_raw(decoded.Op)?,
+            flags,
+            operands: [
+                Operand::from_raw(&decoded.Operands[0]),
+                Operand::from_raw(&decoded.Operands[1]),
+            ],
+            segment_override: flags
+                .contains(InstructionFlags::SEGMENT)
+                .then_some(Register {
+                    index: decoded.SegmentOverride,
+                    offset: 0,
+                    count: 2,
+                }),
+        })
+    }
+
+    /// Iterates over the operands that are present, in order.
+    pub fn operands(&self) -> impl Iterator<Item = &Operand> {
+        self.operands.iter().flatten()
+    }
+}
+
+/// Prints NASM syntax, following `PrintInstruction` in sim86_text.cpp.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let wide = self.flags.contains(InstructionFlags::WIDE);
+        let mut operands = self.operands;
+
+        if self.flags.contains(InstructionFlags::LOCK) {
+            // NASM expects the memory operand first
+            if self.operation == Operation::Xchg {
+                operands.swap(0, 1);
+            }
+            f.write_str("lock ")?;
+        }
+
+        let mut suffix = "";
+        if self.flags.contains(InstructionFlags::REP) {
+            f.write_str("rep ")?;
+            suffix = if wide { "w" } else { "b" };
+        }
+
+        write!(f, "{}{}", self.operation, suffix)?;
+
+        let first_is_register = matches!(operands[0], Some(Operand::Register(_)));
+        let mut separator = " ";
+        for operand in operands.iter().flatten() {
+            f.write_str(separator)?;
+            separator = ", ";
+
+            match operand {
+                Operand::Register(register) => write!(f, "{}", register)?,
+                Operand::Memory(address) => {
+                    if self.flags.contains(InstructionFlags::FAR) {
+                        f.write_str("far ")?;
+                    }
+                    if address.explicit_segment.is_some() {
+                        write!(f, "{}", address)?;
+                    } else {
+                        if !first_is_register {
+                            f.write_str(if wide { "word " } else { "byte " })?;
+                        }
+                        if let Some(segment) = self.segment_override {
+                            write!(f, "{}:", segment)?;
+                        }
+                        write!(f, "[{}]", address)?;
+                    }
+                }
+                Operand::Immediate(immediate) if immediate.relative => {
+                    write!(f, "${:+}", immediate.value + self.size as i32)?
+                }
+                Operand::Immediate(immediate) => write!(f, "{}", immediate.value)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decodes the first instruction in `source` into the safe representation.
+pub fn decode(source: &[u8]) -> Option<Instruction> {
+    decode_8086_instruction(source).and_then(|decoded| Instruction::from_raw(&decoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operations_round_trip_through_raw() {
+        for &operation in Operation::ALL {
+            assert_eq!(Operation::from_raw(operation.raw()), Some(operation));
+        }
+        assert_eq!(Operation::from_raw(operation_type_Op_None), None);
+        assert_eq!(Operation::from_raw(operation_type_Op_Count), None);
+    }
+
+    #[test]
+    fn displays_nasm_syntax() {
+        let cases: &[(&[u8], &str)] = &[
+            (&[0x89, 0xd9], "mov cx, bx"),
+            (&[0x8b, 0x56, 0xfb], "mov dx, [bp-5]"),
+            (&[0xc6, 0x03, 0x07], "mov byte [bp+di], 7"),
+            (&[0x26, 0x8b, 0x4f, 0x02], "mov cx, es:[bx+2]"),
+            (&[0xf0, 0x86, 0x07], "lock xchg byte [bx], al"),
+            (&[0xf3, 0xa5], "rep movsw"),
+            (&[0x75, 0xfc], "jne $-2"),
+            (&[0x9a, 0xc8, 0x01, 0x7b, 0x00], "call 123:456"),
+            (&[0xff, 0x5e, 0x04], "call far word [bp+4]"),
+            (&[0xa1, 0x10, 0x0e], "mov ax, [3600]"),
+            (&[0xc3], "ret"),
+        ];
+        for (bytes, text) in cases {
+            let decoded = decode(bytes).unwrap();
+            assert_eq!(decoded.size as usize, bytes.len());
+            assert_eq!(decoded.to_string(), *text);
+        }
+    }
+
+    #[test]
+    fn operands_follow_the_type_tag() {
+        let decoded = decode(&[0x81, 0x87, 0x34, 0x12, 0xe8, 0x03]).unwrap();
+        assert_eq!(decoded.operation, Operation::Add);
+        assert!(decoded.flags.contains(InstructionFlags::WIDE));
+        let operands: Vec<_> = decoded.operands().copied().collect();
+        match operands.as_slice() {
+            [Operand::Memory(address), Operand::Immediate(immediate)] => {
+                assert_eq!(address.displacement, 0x1234);
+                assert_eq!(address.terms[0].unwrap().register.name(), "bx");
+                assert_eq!(address.terms[1], None);
+                assert_eq!(immediate.value, 1000);
+                assert!(!immediate.relative);
+            }
+            other => panic!("unexpected operands {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Safe access to the reference instruction table. The table is a static array inside the
+//! library, so encodings can be borrowed for the life of the program.
+
+use std::slice;
+
+use crate::*;
+
+/// What a run of bits in an encoding means. `Bits_End` is not a variant; it terminates the
+/// layout and is never yielded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BitsUsage {
+    Literal,
+    D,
+    S,
+    W,
+    V,
+    Z,
+    Mod,
+    Reg,
+    Rm,
+    Sr,
+    Disp,
+    Data,
+    DispAlwaysW,
+    WMakesDataW,
+    RmRegAlwaysW,
+    RelJmpDisp,
+    Far,
+}
+
+impl BitsUsage {
+    pub fn from_raw(usage: instruction_bits_usage) -> Option<BitsUsage> {
+        Some(match usage {
+            instruction_bits_usage_Bits_Literal => BitsUsage::Literal,
+            instruction_bits_usage_Bits_D => BitsUsage::D,
+            instruction_bits_usage_Bits_S => BitsUsage::S,
+            instruction_bits_usage_Bits_W => BitsUsage::W,
+            instruction_bits_usage_Bits_V => BitsUsage::V,
+            instruction_bits_usage_Bits_Z => BitsUsage::Z,
+            instruction_bits_usage_Bits_MOD => BitsUsage::Mod,
+            instruction_bits_usage_Bits_REG => BitsUsage::Reg,
+            instruction_bits_usage_Bits_RM => BitsUsage::Rm,
+            instruction_bits_usage_Bits_SR => BitsUsage::Sr,
+            instruction_bits_usage_Bits_Disp => BitsUsage::Disp,
+            instruction_bits_usage_Bits_Data => BitsUsage::Data,
+            instruction_bits_usage_Bits_DispAlwaysW => BitsUsage::DispAlwaysW,
+            instruction_bits_usage_Bits_WMakesDataW => BitsUsage::WMakesDataW,
+            instruction_bits_usage_Bits_RMRegAlwaysW => BitsUsage::RmRegAlwaysW,
+            instruction_bits_usage_Bits_RelJMPDisp => BitsUsage::RelJmpDisp,
+            instruction_bits_usage_Bits_Far => BitsUsage::Far,
+            _ => return None,
+        })
+    }
+}
+
+/// One entry of an encoding's layout. Fields with a `bit_count` of zero are implied: they
+/// consume no bits and always take `value`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InstructionBits {
+    pub usage: BitsUsage,
+    pub bit_count: u8,
+    pub shift: u8,
+    pub value: u8,
+}
+
+impl InstructionBits {
+    pub fn is_implied(&self) -> bool {
+        self.bit_count == 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Encoding {
+    pub operation: Operation,
+    pub bits: Vec<InstructionBits>,
+}
+
+impl Encoding {
+    fn from_raw(encoding: &instruction_encoding) -> Encoding {
+        Encoding {
+            operation: Operation::from_raw(encoding.Op)
+                .expect("reference table entries always have an operation"),
+            bits: encoding
+                .Bits
+                .iter()
+                .map_while(|bits| {
+                    Some(InstructionBits {
+                        usage: BitsUsage::from_raw(bits.Usage)?,
+                        bit_count: bits.BitCount,
+                        shift: bits.Shift,
+                        value: bits.Value,
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Iterates over the reference 8086 table in table order. See [`encodings`].
+#[derive(Debug, Clone)]
+pub struct Encodings {
+    inner: slice::Iter<'static, instruction_encoding>,
+}
+
+impl Iterator for Encodings {
+    type Item = Encoding;
+
+    fn next(&mut self) -> Option<Encoding> {
+        self.inner.next().map(Encoding::from_raw)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl ExactSizeIterator for Encodings {}
+
+/// Every encoding in the reference 8086 table, including the alternate forms.
+pub fn encodings() -> Encodings {
+    let table = get_8086_instruction_table();
+    // SAFETY: the table points at a static array of `EncodingCount` entries that is never
+    // written after initialisation
+    let encodings = unsafe {
+        slice::from_raw_parts(
+            table.Encodings as *const instruction_encoding,
+            table.EncodingCount as usize,
+        )
+    };
+    Encodings {
+        inner: encodings.iter(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iterates_the_whole_table() {
+        let table = get_8086_instruction_table();
+        assert_eq!(encodings().len(), table.EncodingCount as usize);
+        assert!(encodings().all(|encoding| encoding.bits[0].usage == BitsUsage::Literal));
+    }
+
+    #[test]
+    fn first_encoding_is_register_mov() {
+        let bits = |usage, bit_count, value| InstructionBits {
+            usage,
+            bit_count,
+            shift: 0,
+            value,
+        };
+        let first = encodings().next().unwrap();
+        assert_eq!(first.operation, Operation::Mov);
+        assert_eq!(
+            first.bits,
+            [
+                bits(BitsUsage::Literal, 6, 0b100010),
+                bits(BitsUsage::D, 1, 0),
+                bits(BitsUsage::W, 1, 0),
+                bits(BitsUsage::Mod, 2, 0),
+                bits(BitsUsage::Reg, 3, 0),
+                bits(BitsUsage::Rm, 3, 0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,596 @@
+//! Assembles the NASM dialect the listings are written in: `bits 16`, labels, `byte`, `word`
+//! and `far` size keywords, `[bp + si - 4]` addressing with segment overrides, LOCK and REP
+//! prefixes, `$+N` jumps and `db`. Encodings come from `encoding_table`, so whatever the
+//! disassembler prints assembles back to the bytes it came from. The exception is the
+//! undocumented aliases at the end of `INSTRUCTION_TABLE`: they print as the instruction
+//! they repeat and assemble to its documented encoding.
+
+use std::collections::HashMap;
+
+use crate::encoding_table::encode;
+use crate::error::{AssembleError, AssembleErrorKind};
+use crate::instruction::{
+    EffectiveAddress, EffectiveAddressBase, Instruction, Mnemonic, Operand, Prefixes, Repeat, Width,
+};
+use crate::instruction_table::{Field, INSTRUCTION_TABLE};
+use crate::registers::{Reg16, Register, SegReg};
+
+/// Forward jumps can only grow from short to near, so this is plenty.
+const MAX_PASSES: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Atom {
+    Number(i64),
+    Label(String),
+    /// `$`, the address of the current instruction.
+    Here,
+}
+
+/// A sum of signed products, which covers everything the listings write, e.g. `$+4` or
+/// `61*64*4 + 1`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Expression {
+    terms: Vec<(i64, Vec<Atom>)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Argument {
+    Register(Register),
+    Memory {
+        segment: Option<SegReg>,
+        base: Option<EffectiveAddressBase>,
+        displacement: Option<Expression>,
+    },
+    Value(Expression),
+    Far {
+        segment: Expression,
+        offset: Expression,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Parsed {
+    mnemonic: Mnemonic,
+    prefixes: Prefixes,
+    width: Option<Width>,
+    far: bool,
+    arguments: Vec<Argument>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Statement {
+    Label(String),
+    Bytes(Vec<Expression>),
+    Instruction(Parsed),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Line {
+    number: usize,
+    statement: Statement,
+}
+
+fn syntax(message: impl Into<String>) -> AssembleErrorKind {
+    AssembleErrorKind::Syntax(message.into())
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let text = text.to_ascii_lowercase();
+    if let Some(hex) = text.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = text.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()
+    } else if let Some(hex) = text.strip_suffix('h') {
+        i64::from_str_radix(hex, 16).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// Splits `bp+si-0x3a` into `[(1, "bp"), (1, "si"), (-1, "0x3a")]`.
+fn signed_terms(text: &str) -> Result<Vec<(i64, String)>, AssembleErrorKind> {
+    let mut terms = Vec::new();
+    let mut sign = 1;
+    let mut term = String::new();
+    for c in text.chars().filter(|c| !c.is_whitespace()) {
+        if c == '+' || c == '-' {
+            if !term.is_empty() {
+                terms.push((sign, std::mem::take(&mut term)));
+                sign = 1;
+            }
+            if c == '-' {
+                sign = -sign;
+            }
+        } else {
+            term.push(c);
+        }
+    }
+    if term.is_empty() {
+        return Err(syntax(format!("missing value in `{}`", text)));
+    }
+    terms.push((sign, term));
+    Ok(terms)
+}
+
+fn parse_atom(text: &str) -> Result<Atom, AssembleErrorKind> {
+    if text == "$" {
+        Ok(Atom::Here)
+    } else if text.starts_with(|c: char| c.is_ascii_digit()) {
+        parse_number(text)
+            .map(Atom::Number)
+            .ok_or_else(|| syntax(format!("invalid number `{}`", text)))
+    } else if is_identifier(text) && Register::from_name(text).is_none() {
+        Ok(Atom::Label(text.to_string()))
+    } else {
+        Err(syntax(format!("invalid expression `{}`", text)))
+    }
+}
+
+fn parse_product(term: &str) -> Result<Vec<Atom>, AssembleErrorKind> {
+    term.split('*').map(parse_atom).collect()
+}
+
+fn parse_expression(text: &str) -> Result<Expression, AssembleErrorKind> {
+    let terms = signed_terms(text)?
+        .into_iter()
+        .map(|(sign, term)| Ok((sign, parse_product(&term)?)))
+        .collect::<Result<_, AssembleErrorKind>>()?;
+    Ok(Expression { terms })
+}
+
+fn parse_segment(text: &str) -> Result<Option<SegReg>, AssembleErrorKind> {
+    match text.strip_suffix(':') {
+        None if text.is_empty() => Ok(None),
+        Some(name) => match Register::from_name(name) {
+            Some(Register::Segment(segment)) => Ok(Some(segment)),
+            _ => Err(syntax(format!("`{}` is not a segment register", name))),
+        },
+        None => Err(syntax(format!("unexpected `{}`", text))),
+    }
+}
+
+/// Parses the inside of `[...]`: base registers and a displacement, in any order.
+fn parse_address(
+    text: &str,
+) -> Result<(Option<EffectiveAddressBase>, Option<Expression>), AssembleErrorKind> {
+    let mut registers = Vec::new();
+    let mut displacement = Vec::new();
+    for (sign, term) in signed_terms(text)? {
+        match Register::from_name(&term) {
+            Some(Register::Word(register)) if sign == 1 => registers.push(register),
+            Some(_) => return Err(syntax(format!("invalid effective address `{}`", text))),
+            None => displacement.push((sign, parse_product(&term)?)),
+        }
+    }
+
+    let base = if registers.is_empty() {
+        None
+    } else {
+        let mut registers: Vec<Reg16> = registers;
+        registers.sort_by_key(Reg16::field);
+        let base = EffectiveAddressBase::ALL.into_iter().find(|base| {
+            let mut candidate = base.registers().to_vec();
+            candidate.sort_by_key(Reg16::field);
+            candidate == registers
+        });
+        Some(base.ok_or_else(|| syntax(format!("invalid effective address `{}`", text)))?)
+    };
+    let displacement = (!displacement.is_empty()).then_some(Expression {
+        terms: displacement,
+    });
+    Ok((base, displacement))
+}
+
+fn parse_argument(text: &str) -> Result<Argument, AssembleErrorKind> {
+    if let Some(open) = text.find('[') {
+        let close = text
+            .rfind(']')
+            .filter(|close| text[close + 1..].trim().is_empty())
+            .ok_or_else(|| syntax(format!("unbalanced brackets in `{}`", text)))?;
+        let mut segment = parse_segment(text[..open].trim())?;
+        let mut inside = text[open + 1..close].trim();
+        // NASM also takes the override inside the brackets: `[es:bx]`
+        if let Some((name, rest)) = inside.split_once(':') {
+            segment = parse_segment(&format!("{}:", name.trim()))?;
+            inside = rest;
+        }
+        let (base, displacement) = parse_address(inside)?;
+        return Ok(Argument::Memory {
+            segment,
+            base,
+            displacement,
+        });
+    }
+    if let Some(register) = Register::from_name(text) {
+        return Ok(Argument::Register(register));
+    }
+    if let Some((segment, offset)) = text.split_once(':') {
+        return Ok(Argument::Far {
+            segment: parse_expression(segment)?,
+            offset: parse_expression(offset)?,
+        });
+    }
+    Ok(Argument::Value(parse_expression(text)?))
+}
+
+fn parse_mnemonic(word: &str) -> Result<(Mnemonic, Option<Width>), AssembleErrorKind> {
+    let lower = word.to_ascii_lowercase();
+    let name = match lower.as_str() {
+        "jz" => "je",
+        "jnz" => "jne",
+        "jnge" => "jl",
+        "jge" => "jnl",
+        "jng" => "jle",
+        "jnle" => "jg",
+        "jc" | "jnae" => "jb",
+        "jnc" | "jae" => "jnb",
+        "jna" => "jbe",
+        "jnbe" => "ja",
+        "jpe" => "jp",
+        "jpo" => "jnp",
+        "loope" => "loopz",
+        "loopne" => "loopnz",
+        "sal" => "shl",
+        "xlatb" => "xlat",
+        other => other,
+    };
+    let unknown = || AssembleErrorKind::UnknownMnemonic(word.to_string());
+
+    // String instructions carry their width in the name: MOVSB, MOVSW
+    let string = [("b", Width::Byte), ("w", Width::Word)]
+        .into_iter()
+        .find_map(|(suffix, width)| {
+            let mnemonic = Mnemonic::from_name(name.strip_suffix(suffix)?)?;
+            mnemonic.is_string().then_some((mnemonic, Some(width)))
+        });
+    match string.or_else(|| Mnemonic::from_name(name).map(|mnemonic| (mnemonic, None))) {
+        Some((Mnemonic::Lock | Mnemonic::Rep | Mnemonic::Segment, _)) | None => Err(unknown()),
+        Some(found) => Ok(found),
+    }
+}
+
+fn parse_instruction(text: &str) -> Result<Parsed, AssembleErrorKind> {
+    let mut prefixes = Prefixes::default();
+    let mut rest = text;
+    let (mnemonic, width) = loop {
+        let (word, after) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        rest = after.trim();
+        match word.to_ascii_lowercase().as_str() {
+            "lock" => prefixes.lock = true,
+            "rep" | "repe" | "repz" => prefixes.rep = Some(Repeat::Rep),
+            "repne" | "repnz" => prefixes.rep = Some(Repeat::Repne),
+            // A segment override for an instruction without a memory operand: `es movsb`
+            lowered => match Register::from_name(lowered) {
+                Some(Register::Segment(segment)) => prefixes.segment = Some(segment),
+                _ => break parse_mnemonic(word)?,
+            },
+        }
+        if rest.is_empty() {
+            return Err(syntax("prefix without an instruction"));
+        }
+    };
+
+    let mut parsed = Parsed {
+        mnemonic,
+        prefixes,
+        width,
+        far: false,
+        arguments: Vec::new(),
+    };
+    if rest.is_empty() {
+        return Ok(parsed);
+    }
+    for text in rest.split(',') {
+        let mut words = Vec::new();
+        for word in text.split_whitespace() {
+            let width = match word.to_ascii_lowercase().as_str() {
+                "byte" => Some(Width::Byte),
+                "word" => Some(Width::Word),
+                "far" => {
+                    parsed.far = true;
+                    continue;
+                }
+                // The shortest jump that reaches is picked anyway
+                "short" | "near" => continue,
+                _ => None,
+            };
+            match (width, parsed.width) {
+                (Some(width), Some(previous)) if width != previous => {
+                    return Err(syntax("conflicting operand sizes"))
+                }
+                (Some(width), _) => parsed.width = Some(width),
+                (None, _) => words.push(word),
+            }
+        }
+        let argument = parse_argument(&words.join(" "))?;
+        if let Argument::Memory {
+            segment: Some(segment),
+            ..
+        } = argument
+        {
+            parsed.prefixes.segment = Some(segment);
+        }
+        parsed.arguments.push(argument);
+    }
+
+    // NASM encodes this as 0x90, which is NOP
+    let ax = Argument::Register(Register::Word(Reg16::Ax));
+    if parsed.mnemonic == Mnemonic::Xchg && parsed.arguments == [ax.clone(), ax] {
+        parsed.mnemonic = Mnemonic::Nop;
+        parsed.arguments.clear();
+    }
+    Ok(parsed)
+}
+
+/// Parses one line into its label, if any, and its statement, if any.
+fn parse_line(text: &str) -> Result<Vec<Statement>, AssembleErrorKind> {
+    let mut text = text.split(';').next().unwrap_or("").trim();
+    let mut statements = Vec::new();
+    if let Some((label, rest)) = text.split_once(':') {
+        if is_identifier(label) && Register::from_name(label).is_none() {
+            statements.push(Statement::Label(label.to_string()));
+            text = rest.trim();
+        }
+    }
+    if text.is_empty() {
+        return Ok(statements);
+    }
+
+    let (word, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    match word.to_ascii_lowercase().as_str() {
+        "bits" => {
+            if rest.trim() != "16" {
+                return Err(syntax("only 16-bit code is supported"));
+            }
+        }
+        "db" => statements.push(Statement::Bytes(
+            rest.split(',')
+                .map(parse_expression)
+                .collect::<Result<_, _>>()?,
+        )),
+        _ => statements.push(Statement::Instruction(parse_instruction(text)?)),
+    }
+    Ok(statements)
+}
+
+fn parse(source: &str) -> Result<Vec<Line>, AssembleError> {
+    let mut lines = Vec::new();
+    for (index, text) in source.lines().enumerate() {
+        let number = index + 1;
+        let statements = parse_line(text).map_err(|kind| AssembleError { line: number, kind })?;
+        lines.extend(
+            statements
+                .into_iter()
+                .map(|statement| Line { number, statement }),
+        );
+    }
+    Ok(lines)
+}
+
+/// Label addresses as of the current pass. A label that has not been placed yet stands for
+/// the address being assembled, which keeps forward jumps short until proven otherwise.
+struct Symbols {
+    addresses: HashMap<String, Option<usize>>,
+}
+
+impl Symbols {
+    fn evaluate(&self, expression: &Expression, here: usize) -> Result<i64, AssembleErrorKind> {
+        let mut total = 0i64;
+        for (sign, product) in &expression.terms {
+            let mut value = *sign;
+            for atom in product {
+                value = value.wrapping_mul(match atom {
+                    Atom::Number(number) => *number,
+                    Atom::Here => here as i64,
+                    Atom::Label(name) => match self.addresses.get(name) {
+                        Some(address) => address.unwrap_or(here) as i64,
+                        None => return Err(AssembleErrorKind::UndefinedLabel(name.clone())),
+                    },
+                });
+            }
+            total = total.wrapping_add(value);
+        }
+        Ok(total)
+    }
+
+    fn evaluate_i32(&self, expression: &Expression, here: usize) -> Result<i32, AssembleErrorKind> {
+        let value = self.evaluate(expression, here)?;
+        i32::try_from(value).map_err(|_| syntax(format!("{} is out of range", value)))
+    }
+}
+
+/// Mnemonics whose lone value operand is a jump target rather than an immediate.
+fn is_branch(mnemonic: Mnemonic) -> bool {
+    INSTRUCTION_TABLE.iter().any(|encoding| {
+        encoding.mnemonic == mnemonic && encoding.fields.contains(&Field::RelJmpDisp)
+    })
+}
+
+fn assemble_statement(
+    statement: &Statement,
+    address: usize,
+    symbols: &Symbols,
+) -> Result<Vec<u8>, AssembleErrorKind> {
+    let parsed = match statement {
+        Statement::Label(_) => return Ok(Vec::new()),
+        Statement::Bytes(values) => {
+            return values
+                .iter()
+                .map(|value| {
+                    let value = symbols.evaluate(value, address)?;
+                    match value {
+                        -128..=255 => Ok(value as u8),
+                        _ => Err(syntax(format!("{} does not fit in a byte", value))),
+                    }
+                })
+                .collect();
+        }
+        Statement::Instruction(parsed) => parsed,
+    };
+
+    let mut branch_target = None;
+    let mut operands = Vec::new();
+    for argument in &parsed.arguments {
+        operands.push(match argument {
+            Argument::Register(register) => Operand::Register(*register),
+            Argument::Memory {
+                base, displacement, ..
+            } => {
+                let displacement = match displacement {
+                    Some(displacement) => Some(symbols.evaluate_i32(displacement, address)?),
+                    None if base.is_none() => Some(0),
+                    None => None,
+                };
+                Operand::Memory(EffectiveAddress {
+                    base: *base,
+                    displacement,
+                })
+            }
+            Argument::Value(value) if is_branch(parsed.mnemonic) => {
+                branch_target = Some(symbols.evaluate(value, address)? as u16);
+                Operand::Relative(0)
+            }
+            Argument::Value(value) => Operand::Immediate(symbols.evaluate_i32(value, address)?),
+            Argument::Far { segment, offset } => Operand::Far {
+                segment: symbols.evaluate(segment, address)? as u16,
+                offset: symbols.evaluate(offset, address)? as u16,
+            },
+        });
+    }
+
+    let mut instruction = Instruction::new(parsed.mnemonic, operands);
+    instruction.address = address;
+    instruction.width = parsed.width;
+    instruction.far = parsed.far;
+    instruction.prefixes = parsed.prefixes;
+    encode(&instruction, branch_target).map_err(AssembleErrorKind::Encode)
+}
+
+/// Assembles `source` into a flat binary loaded at offset 0.
+///
+/// Jumps take the shortest form that reaches, so label addresses are settled over several
+/// passes; the first pass assumes every forward label is right where the jump is.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssembleError> {
+    let lines = parse(source)?;
+    let mut symbols = Symbols {
+        addresses: HashMap::new(),
+    };
+    for line in &lines {
+        if let Statement::Label(name) = &line.statement {
+            if symbols.addresses.insert(name.clone(), None).is_some() {
+                return Err(AssembleError {
+                    line: line.number,
+                    kind: AssembleErrorKind::DuplicateLabel(name.clone()),
+                });
+            }
+        }
+    }
+
+    // A statement that fails to encode keeps its length from the last pass that managed to,
+    // since the failure may only be down to labels that have not settled yet
+    let mut lengths = vec![0; lines.len()];
+    for _ in 0..MAX_PASSES {
+        let mut output = Vec::new();
+        let mut first_error = None;
+        let mut settled = true;
+        for (line, length) in lines.iter().zip(&mut lengths) {
+            let address = output.len();
+            if let Statement::Label(name) = &line.statement {
+                let previous = symbols.addresses.insert(name.clone(), Some(address));
+                settled &= previous == Some(Some(address));
+            }
+            match assemble_statement(&line.statement, address, &symbols) {
+                Ok(bytes) => {
+                    *length = bytes.len();
+                    output.extend(bytes);
+                }
+                Err(kind) => {
+                    first_error.get_or_insert(AssembleError {
+                        line: line.number,
+                        kind,
+                    });
+                    output.resize(address + *length, 0);
+                }
+            }
+        }
+        if settled {
+            return match first_error {
+                Some(error) => Err(error),
+                None => Ok(output),
+            };
+        }
+    }
+    Err(AssembleError {
+        line: 0,
+        kind: AssembleErrorKind::Unsettled,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forward_jumps_grow_to_near_when_short_does_not_reach() {
+        let mut source = String::from("bits 16\njmp done\n");
+        for _ in 0..100 {
+            source.push_str("mov ax, bx\n");
+        }
+        source.push_str("done:\njne $+2\n");
+        let binary = assemble(&source).unwrap();
+        assert_eq!(&binary[..3], &[0xe9, 200, 0]);
+        assert_eq!(&binary[203..], &[0x75, 0x00]);
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let error = assemble("bits 16\n\nmov ax, bx\ninc [bx]\n").unwrap_err();
+        assert_eq!(error.line, 4);
+        assert_eq!(
+            error.kind,
+            AssembleErrorKind::Encode(crate::EncodeError::AmbiguousSize)
+        );
+        let error = assemble("jmp nowhere").unwrap_err();
+        assert_eq!(
+            error.kind,
+            AssembleErrorKind::UndefinedLabel("nowhere".to_string())
+        );
+    }
+
+    #[test]
+    fn prefixes_and_overrides() {
+        assert_eq!(
+            assemble("lock not byte CS:[bp + 9905]\nrep movsb\nmov al, [es:bx]").unwrap(),
+            [0xf0, 0x2e, 0xf6, 0x96, 0xb1, 0x26, 0xf3, 0xa4, 0x26, 0x8a, 0x07]
+        );
+        assert_eq!(
+            assemble("es movsb\nrep es movsw").unwrap(),
+            [0x26, 0xa4, 0xf3, 0x26, 0xa5]
+        );
+    }
+
+    #[test]
+    fn aliases_assemble_to_the_documented_encoding() {
+        let cases: [(&[u8], &[u8]); 5] = [
+            (&[0xc0, 0x34, 0x12], &[0xc2, 0x34, 0x12]),
+            (&[0x60, 0x05], &[0x70, 0x05]),
+            (&[0xf1, 0xf6, 0x17], &[0xf0, 0xf6, 0x17]),
+            (&[0x8f, 0xc8], &[0x58]),
+            (&[0x8e, 0xe0], &[0x8e, 0xc0]),
+        ];
+        for (alias, documented) in cases {
+            let text = crate::Decoder::at(alias, 0)
+                .next()
+                .unwrap()
+                .unwrap()
+                .to_string();
+            assert_eq!(assemble(&text).unwrap(), documented, "{}", text);
+        }
+    }
+}
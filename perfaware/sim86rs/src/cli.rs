@@ -0,0 +1,383 @@
+//! Command-line parsing. Options may come before or after the file names and take their
+//! value either as `--option=value` or as the next argument. Without a subcommand a lone
+//! file is disassembled, as it always was.
+
+use sim86rs::clocks::Cpu;
+use sim86rs::listing::Layout;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Disasm,
+    Exec,
+    Cycles,
+    Assemble,
+    /// Disassembles two binaries and reports the instructions that differ.
+    Diff,
+}
+
+impl Command {
+    fn from_name(name: &str) -> Option<Command> {
+        match name {
+            "disasm" => Some(Command::Disasm),
+            "exec" => Some(Command::Exec),
+            "cycles" => Some(Command::Cycles),
+            "assemble" => Some(Command::Assemble),
+            "diff" => Some(Command::Diff),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Command::Disasm => "disasm",
+            Command::Exec => "exec",
+            Command::Cycles => "cycles",
+            Command::Assemble => "assemble",
+            Command::Diff => "diff",
+        }
+    }
+
+    /// Whether `option` means anything to this command. Options that would be silently
+    /// ignored are rejected instead.
+    fn takes(self, option: &str) -> bool {
+        use Command::*;
+        match option {
+            "--format" | "--address-base" | "--bytes-width" => self == Disasm,
+            "--labels" | "--on-error" => matches!(self, Disasm | Diff),
+            "--start" | "--length" | "--load-address" => self != Assemble,
+            "--cpu" => self == Cycles,
+            "--trace" => matches!(self, Exec | Cycles),
+            _ => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    /// JSON Lines: one object per instruction.
+    Json,
+    /// Addresses and raw bytes next to the text, like objdump.
+    Listing(Layout),
+}
+
+/// How much of an execution to print.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trace {
+    /// Every instruction with every change, IP included.
+    Full,
+    /// Every instruction, without IP changes or the final IP.
+    NoIp,
+    /// Only the final registers.
+    Final,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    pub command: Command,
+    /// Input files; `-` is stdin.
+    pub inputs: Vec<String>,
+    /// Where to write the output instead of stdout. Required by `assemble`.
+    pub output: Option<String>,
+    pub format: Format,
+    /// Byte offset into the input to start at.
+    pub start: usize,
+    /// How many bytes of the input to use, from `start`.
+    pub length: Option<usize>,
+    /// Offset in the code segment that the first byte used is loaded at.
+    pub load_address: u16,
+    /// CPU models to estimate clocks for, one trace each.
+    pub cpus: Vec<Cpu>,
+    pub trace: Trace,
+    pub labels: bool,
+    pub resync: bool,
+    /// Number of `-v` flags.
+    pub verbosity: usize,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            command: Command::Disasm,
+            inputs: Vec::new(),
+            output: None,
+            format: Format::Text,
+            start: 0,
+            length: None,
+            load_address: 0,
+            cpus: Vec::new(),
+            trace: Trace::Full,
+            labels: false,
+            resync: false,
+            verbosity: 0,
+        }
+    }
+}
+
+pub fn usage(program: &str) -> String {
+    format!(
+        "Usage: {program} [command] [options] <file>
+
+Commands:
+  disasm <file|->         Disassemble to NASM syntax (the default)
+  exec <file>             Simulate and trace every instruction
+  cycles <file>           Simulate with 8086/8088 clock estimates
+  assemble <source>       Assemble NASM source into the file given with -o
+  diff <a> <b>            Disassemble both files and print the instructions that differ
+
+Options:
+  -o, --output <path>     Write to <path> instead of stdout
+  --format <text|json|listing>
+                          Disassembly as NASM text, JSON Lines, or a listing with
+                          addresses and raw bytes
+  --address-base <seg>    Segment shown before each offset in a listing (default: 0)
+  --bytes-width <count>   Bytes per listing line (default: 6)
+  --labels                Name branch targets instead of printing $+N
+  --on-error <stop|resync>
+                          Stop at an undecodable byte, or emit it as data and go on
+  --start <offset>        Skip this many bytes of the input
+  --length <count>        Use at most this many bytes, from the start offset
+  --load-address <addr>   Code segment offset the first byte used is loaded at
+  --cpu <8086|8088>       CPU model for cycles (default: both, one after the other)
+  --trace <full|no-ip|final>
+                          How much of an execution to print
+  -v, -vv, -vvv           More diagnostics on stderr; RUST_LOG also works
+
+Numbers may be decimal or 0x hexadecimal."
+    )
+}
+
+fn number(option: &str, value: &str) -> Result<usize, String> {
+    let parsed = match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|_| format!("{} expects a number, not '{}'", option, value))
+}
+
+fn cpu(value: &str) -> Result<Cpu, String> {
+    Cpu::ALL
+        .into_iter()
+        .find(|cpu| cpu.to_string() == value)
+        .ok_or_else(|| format!("unknown CPU model '{}'", value))
+}
+
+/// Parses the arguments after the program name.
+pub fn parse(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut command = None;
+    let mut layout = Layout::default();
+    let mut given = Vec::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => (name, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| args.next().cloned())
+                .ok_or_else(|| format!("{} expects a value", name))
+        };
+        given.push(name);
+        match name {
+            "-o" | "--output" => options.output = Some(value()?),
+            "--format" => {
+                options.format = match value()?.as_str() {
+                    "text" => Format::Text,
+                    "json" => Format::Json,
+                    "listing" => Format::Listing(Layout::default()),
+                    other => return Err(format!("unknown format '{}'", other)),
+                }
+            }
+            "--labels" => options.labels = true,
+            "--on-error" => {
+                options.resync = match value()?.as_str() {
+                    "stop" => false,
+                    "resync" => true,
+                    other => return Err(format!("unknown error handling '{}'", other)),
+                }
+            }
+            "--start" => options.start = number(name, &value()?)?,
+            "--length" => options.length = Some(number(name, &value()?)?),
+            "--load-address" => {
+                let address = number(name, &value()?)?;
+                options.load_address = u16::try_from(address)
+                    .map_err(|_| format!("load address {:#x} is outside the segment", address))?;
+            }
+            "--address-base" => {
+                let segment = number(name, &value()?)?;
+                layout.segment = u16::try_from(segment)
+                    .map_err(|_| format!("address base {:#x} is not a segment", segment))?;
+            }
+            "--bytes-width" => {
+                layout.bytes_per_line = match number(name, &value()?)? {
+                    0 => return Err("--bytes-width must be at least 1".to_string()),
+                    width => width,
+                }
+            }
+            "--cpu" => options.cpus = vec![cpu(&value()?)?],
+            "--trace" => {
+                options.trace = match value()?.as_str() {
+                    "full" => Trace::Full,
+                    "no-ip" => Trace::NoIp,
+                    "final" => Trace::Final,
+                    other => return Err(format!("unknown trace level '{}'", other)),
+                }
+            }
+            _ if arg
+                .strip_prefix('-')
+                .is_some_and(|vs| !vs.is_empty() && vs.chars().all(|c| c == 'v')) =>
+            {
+                options.verbosity += arg.len() - 1
+            }
+            _ if arg.starts_with('-') && arg != "-" => {
+                return Err(format!("unknown option '{}'", arg))
+            }
+            _ => match Command::from_name(arg) {
+                Some(named) if command.is_none() && options.inputs.is_empty() => {
+                    command = Some(named)
+                }
+                _ => options.inputs.push(arg.clone()),
+            },
+        }
+    }
+
+    options.command = command.unwrap_or(Command::Disasm);
+    if let Some(option) = given.iter().find(|option| !options.command.takes(option)) {
+        return Err(format!(
+            "{} does not apply to {}",
+            option,
+            options.command.name()
+        ));
+    }
+    // The layout options may come before or after --format
+    if let Format::Listing(listing) = &mut options.format {
+        *listing = layout;
+    }
+    let inputs = match options.command {
+        Command::Diff => 2,
+        Command::Assemble if options.output.is_none() => {
+            return Err("assemble needs an output file".to_string())
+        }
+        _ => 1,
+    };
+    if options.inputs.len() != inputs {
+        return Err(format!(
+            "expected {} input file{}, got {}",
+            inputs,
+            if inputs == 1 { "" } else { "s" },
+            options.inputs.len()
+        ));
+    }
+    if options.command == Command::Cycles && options.cpus.is_empty() {
+        options.cpus = Cpu::ALL.to_vec();
+    }
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_line(line: &str) -> Result<Options, String> {
+        let args: Vec<String> = line.split_whitespace().map(String::from).collect();
+        parse(&args)
+    }
+
+    #[test]
+    fn subcommands_take_options_anywhere() {
+        let options =
+            parse_line("disasm --start 0x10 listing --length=4 -o out.asm --format json -vv")
+                .unwrap();
+        assert_eq!(options.command, Command::Disasm);
+        assert_eq!(options.inputs, ["listing"]);
+        assert_eq!(options.start, 16);
+        assert_eq!(options.length, Some(4));
+        assert_eq!(options.output.as_deref(), Some("out.asm"));
+        assert_eq!(options.format, Format::Json);
+        assert_eq!(options.verbosity, 2);
+
+        let options = parse_line("cycles --cpu=8088 --trace final listing").unwrap();
+        assert_eq!(options.cpus, [Cpu::I8088]);
+        assert_eq!(options.trace, Trace::Final);
+        assert_eq!(parse_line("cycles listing").unwrap().cpus, Cpu::ALL);
+
+        let options = parse_line("diff a b").unwrap();
+        assert_eq!(options.command, Command::Diff);
+        assert_eq!(options.inputs, ["a", "b"]);
+
+        let options =
+            parse_line("--bytes-width 8 disasm a --format=listing --address-base=0x1000").unwrap();
+        assert_eq!(
+            options.format,
+            Format::Listing(Layout {
+                segment: 0x1000,
+                bytes_per_line: 8,
+            })
+        );
+    }
+
+    #[test]
+    fn commands_pick_the_mode() {
+        let options = parse_line("cycles --cpu 8088 listing").unwrap();
+        assert_eq!(options.command, Command::Cycles);
+        assert_eq!(options.cpus, [Cpu::I8088]);
+        assert_eq!(options.trace, Trace::Full);
+
+        let options = parse_line("exec --trace=no-ip listing").unwrap();
+        assert_eq!(options.command, Command::Exec);
+        assert!(options.cpus.is_empty());
+        assert_eq!(options.trace, Trace::NoIp);
+
+        let options = parse_line("assemble source.asm -o out.bin").unwrap();
+        assert_eq!(options.inputs, ["source.asm"]);
+        assert_eq!(options.output.as_deref(), Some("out.bin"));
+
+        let options = parse_line("--on-error=resync --labels -").unwrap();
+        assert_eq!(options.command, Command::Disasm);
+        assert_eq!(options.inputs, ["-"]);
+        assert!(options.resync && options.labels);
+    }
+
+    #[test]
+    fn mistakes_are_reported() {
+        assert!(parse_line("disasm").is_err());
+        assert!(parse_line("diff a").is_err());
+        assert!(parse_line("assemble source.asm").is_err());
+        assert!(parse_line("assemble source.asm out.bin").is_err());
+        assert!(parse_line("disasm --start").is_err());
+        assert!(parse_line("disasm --start=ten a").is_err());
+        assert!(parse_line("cycles --cpu=80286 a").is_err());
+        assert!(parse_line("disasm --bogus a").is_err());
+        assert!(parse_line("exec --load-address=0x10000 a").is_err());
+        assert!(parse_line("disasm --format listing --bytes-width 0 a").is_err());
+
+        for (line, message) in [
+            ("exec --format json a", "--format does not apply to exec"),
+            ("cycles --labels a", "--labels does not apply to cycles"),
+            (
+                "diff --format=listing a b",
+                "--format does not apply to diff",
+            ),
+            ("exec --cpu 8086 a", "--cpu does not apply to exec"),
+            ("disasm --cpu 8088 a", "--cpu does not apply to disasm"),
+            (
+                "assemble --start 4 a -o b",
+                "--start does not apply to assemble",
+            ),
+            (
+                "--length=4 assemble a -o b",
+                "--length does not apply to assemble",
+            ),
+            ("a --trace final", "--trace does not apply to disasm"),
+        ] {
+            assert_eq!(parse_line(line).unwrap_err(), message);
+        }
+    }
+}
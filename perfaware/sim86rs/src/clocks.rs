@@ -0,0 +1,406 @@
+//! Clock estimates from the instruction timings in the 8086 manual (table 2-21), split the
+//! way the course prints them: base clocks, effective-address clocks and the penalty for
+//! word transfers the bus has to make in two halves.
+
+use std::fmt;
+
+use crate::instruction::{
+    EffectiveAddress, EffectiveAddressBase, Instruction, Mnemonic, Operand, Width,
+};
+use crate::registers::{Reg16, Reg8, Register, IP};
+use crate::simulator::{effective_offset, Snapshot};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cpu {
+    /// 16-bit bus: a word transfer only costs extra when its address is odd.
+    I8086,
+    /// 8-bit bus: every word transfer costs extra.
+    I8088,
+}
+
+impl Cpu {
+    pub const ALL: [Cpu; 2] = [Cpu::I8086, Cpu::I8088];
+}
+
+impl fmt::Display for Cpu {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Cpu::I8086 => write!(f, "8086"),
+            Cpu::I8088 => write!(f, "8088"),
+        }
+    }
+}
+
+/// Clocks for one instruction.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Clocks {
+    pub base: u32,
+    /// Effective-address calculation, including 2 for a segment override.
+    pub effective_address: u32,
+    /// 4 per word transfer split into two bus cycles.
+    pub penalty: u32,
+}
+
+impl Clocks {
+    pub fn total(&self) -> u32 {
+        self.base + self.effective_address + self.penalty
+    }
+}
+
+/// The breakdown, e.g. ` (8 + 6ea + 4p)`, or nothing when there is only a base.
+impl fmt::Display for Clocks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.effective_address == 0 && self.penalty == 0 {
+            return Ok(());
+        }
+        write!(f, " ({}", self.base)?;
+        if self.effective_address != 0 {
+            write!(f, " + {}ea", self.effective_address)?;
+        }
+        if self.penalty != 0 {
+            write!(f, " + {}p", self.penalty)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Clocks to compute an effective address.
+fn effective_address_clocks(instruction: &Instruction, address: &EffectiveAddress) -> u32 {
+    // A zero displacement is only there because [BP] cannot be encoded without one
+    let displacement = address
+        .displacement
+        .is_some_and(|displacement| displacement != 0);
+    let clocks = match (address.base, displacement) {
+        (None, _) => 6,
+        (Some(EffectiveAddressBase::BpDi | EffectiveAddressBase::BxSi), false) => 7,
+        (Some(EffectiveAddressBase::BpSi | EffectiveAddressBase::BxDi), false) => 8,
+        (Some(EffectiveAddressBase::BpDi | EffectiveAddressBase::BxSi), true) => 11,
+        (Some(EffectiveAddressBase::BpSi | EffectiveAddressBase::BxDi), true) => 12,
+        (Some(_), false) => 5,
+        (Some(_), true) => 9,
+    };
+    if instruction.prefixes.segment.is_some() {
+        clocks + 2
+    } else {
+        clocks
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// AL or AX, which some forms treat specially.
+    Accumulator,
+    Register,
+    Segment,
+    Memory,
+    Immediate,
+    Far,
+    Other,
+}
+
+fn kind(operand: &Operand) -> Kind {
+    match operand {
+        Operand::Register(Register::Byte(Reg8::Al) | Register::Word(Reg16::Ax)) => {
+            Kind::Accumulator
+        }
+        Operand::Register(Register::Segment(_)) => Kind::Segment,
+        Operand::Register(_) => Kind::Register,
+        Operand::Memory(_) => Kind::Memory,
+        Operand::Immediate(_) => Kind::Immediate,
+        Operand::Far { .. } => Kind::Far,
+        Operand::Relative(_) => Kind::Other,
+    }
+}
+
+/// Base clocks and how many times the memory operand is transferred, from the manual.
+/// Where the manual gives a range (MUL, DIV) this takes the low end.
+fn base_clocks(
+    instruction: &Instruction,
+    before: &Snapshot,
+    after: &Snapshot,
+) -> Option<(u32, u32)> {
+    use Kind::{Accumulator as A, Immediate as I, Memory as M, Register as R, Segment as S};
+
+    let kinds: Vec<Kind> = instruction.operands.iter().map(kind).collect();
+    // Anything that is not AL/AX-specific treats the accumulator as just another register
+    let general: Vec<Kind> = kinds
+        .iter()
+        .map(|kind| if *kind == A { R } else { *kind })
+        .collect();
+    let word = instruction.width == Some(Width::Word);
+    let taken = after.registers.get(IP) as usize != instruction.address + instruction.length;
+    let cl = before.registers.read(Reg8::Cl) as u32;
+    let repeats = || {
+        let cx = Reg16::Cx;
+        before
+            .registers
+            .read(cx)
+            .wrapping_sub(after.registers.read(cx)) as u32
+    };
+    let string = |clocks: u32, per_repeat: u32, transfers: u32| match instruction.prefixes.rep {
+        Some(_) => (9 + per_repeat * repeats(), transfers * repeats()),
+        None => (clocks, transfers),
+    };
+    let far = instruction.far;
+
+    Some(
+        match (instruction.mnemonic, kinds.as_slice(), general.as_slice()) {
+            (Mnemonic::Mov, [A, M] | [M, A], _) if instruction.accumulator_direct => (10, 1),
+            (Mnemonic::Mov, _, [R, R] | [S, R] | [R, S]) => (2, 0),
+            (Mnemonic::Mov, _, [R | S, M]) => (8, 1),
+            (Mnemonic::Mov, _, [M, R | S]) => (9, 1),
+            (Mnemonic::Mov, _, [R, I]) => (4, 0),
+            (Mnemonic::Mov, _, [M, I]) => (10, 1),
+
+            (
+                Mnemonic::Add
+                | Mnemonic::Adc
+                | Mnemonic::Sub
+                | Mnemonic::Sbb
+                | Mnemonic::And
+                | Mnemonic::Or
+                | Mnemonic::Xor,
+                _,
+                operands,
+            ) => match operands {
+                [R, R] => (3, 0),
+                [R, M] => (9, 1),
+                [M, R] => (16, 2),
+                [R, I] => (4, 0),
+                [M, I] => (17, 2),
+                _ => return None,
+            },
+            (Mnemonic::Cmp, _, operands) => match operands {
+                [R, R] => (3, 0),
+                [R, M] | [M, R] => (9, 1),
+                [R, I] => (4, 0),
+                [M, I] => (10, 1),
+                _ => return None,
+            },
+            (Mnemonic::Test, kinds, operands) => match (kinds, operands) {
+                (_, [R, R]) => (3, 0),
+                (_, [R, M] | [M, R]) => (9, 1),
+                ([A, I], _) => (4, 0),
+                (_, [R, I]) => (5, 0),
+                (_, [M, I]) => (11, 1),
+                _ => return None,
+            },
+
+            (Mnemonic::Inc | Mnemonic::Dec, _, [R]) => (if word { 2 } else { 3 }, 0),
+            (Mnemonic::Inc | Mnemonic::Dec, _, [M]) => (15, 2),
+            (Mnemonic::Neg | Mnemonic::Not, _, [R]) => (3, 0),
+            (Mnemonic::Neg | Mnemonic::Not, _, [M]) => (16, 2),
+
+            (Mnemonic::Xchg, [A, R] | [R, A], _) if word => (3, 0),
+            (Mnemonic::Xchg, _, [R, R]) => (4, 0),
+            (Mnemonic::Xchg, _, [R, M] | [M, R]) => (17, 2),
+
+            (Mnemonic::Lea, _, [R, M]) => (2, 0),
+            (Mnemonic::Lds | Mnemonic::Les, _, [R, M]) => (16, 2),
+            (Mnemonic::Push, _, [R]) => (11, 0),
+            (Mnemonic::Push, _, [S]) => (10, 0),
+            (Mnemonic::Push, _, [M]) => (16, 1),
+            (Mnemonic::Pop, _, [R | S]) => (8, 0),
+            (Mnemonic::Pop, _, [M]) => (17, 1),
+
+            (Mnemonic::Mul, _, [R]) => (if word { 118 } else { 70 }, 0),
+            (Mnemonic::Mul, _, [M]) => (if word { 124 } else { 76 }, 1),
+            (Mnemonic::Imul, _, [R]) => (if word { 128 } else { 80 }, 0),
+            (Mnemonic::Imul, _, [M]) => (if word { 134 } else { 86 }, 1),
+            (Mnemonic::Div, _, [R]) => (if word { 144 } else { 80 }, 0),
+            (Mnemonic::Div, _, [M]) => (if word { 150 } else { 86 }, 1),
+            (Mnemonic::Idiv, _, [R]) => (if word { 165 } else { 101 }, 0),
+            (Mnemonic::Idiv, _, [M]) => (if word { 171 } else { 107 }, 1),
+
+            (mnemonic, _, [R, count]) if mnemonic.is_shift() => match count {
+                I => (2, 0),
+                _ => (8 + 4 * cl, 0),
+            },
+            (mnemonic, _, [M, count]) if mnemonic.is_shift() => match count {
+                I => (15, 2),
+                _ => (20 + 4 * cl, 2),
+            },
+
+            (Mnemonic::Movs, _, _) => string(18, 17, 2),
+            (Mnemonic::Cmps, _, _) => string(22, 22, 2),
+            (Mnemonic::Scas, _, _) => string(15, 15, 1),
+            (Mnemonic::Lods, _, _) => string(12, 13, 1),
+            (Mnemonic::Stos, _, _) => string(11, 10, 1),
+
+            (Mnemonic::Jmp, _, [M]) => (if far { 24 } else { 18 }, if far { 2 } else { 1 }),
+            (Mnemonic::Jmp, _, [R]) => (11, 0),
+            (Mnemonic::Jmp, _, _) => (15, 0),
+            (Mnemonic::Call, _, [M]) => (if far { 37 } else { 21 }, if far { 2 } else { 1 }),
+            (Mnemonic::Call, _, [R]) => (16, 0),
+            (Mnemonic::Call, [Kind::Far], _) => (28, 0),
+            (Mnemonic::Call, _, _) => (19, 0),
+            (Mnemonic::Ret, [], _) => (8, 0),
+            (Mnemonic::Ret, _, _) => (12, 0),
+            (Mnemonic::Retf, [], _) => (18, 0),
+            (Mnemonic::Retf, _, _) => (17, 0),
+            (Mnemonic::Loop, _, _) => (if taken { 17 } else { 5 }, 0),
+            (Mnemonic::Loopz | Mnemonic::Jcxz, _, _) => (if taken { 18 } else { 6 }, 0),
+            (Mnemonic::Loopnz, _, _) => (if taken { 19 } else { 5 }, 0),
+            (
+                Mnemonic::Je
+                | Mnemonic::Jl
+                | Mnemonic::Jle
+                | Mnemonic::Jb
+                | Mnemonic::Jbe
+                | Mnemonic::Jp
+                | Mnemonic::Jo
+                | Mnemonic::Js
+                | Mnemonic::Jne
+                | Mnemonic::Jnl
+                | Mnemonic::Jg
+                | Mnemonic::Jnb
+                | Mnemonic::Ja
+                | Mnemonic::Jnp
+                | Mnemonic::Jno
+                | Mnemonic::Jns,
+                _,
+                _,
+            ) => (if taken { 16 } else { 4 }, 0),
+
+            (Mnemonic::In | Mnemonic::Out, kinds, _) if kinds.contains(&I) => (10, 0),
+            (Mnemonic::In | Mnemonic::Out, _, _) => (8, 0),
+            (Mnemonic::Int, _, _) => (51, 0),
+            (Mnemonic::Int3, _, _) => (52, 0),
+            (Mnemonic::Into, _, _) => (if taken { 53 } else { 4 }, 0),
+            (Mnemonic::Iret, _, _) => (24, 0),
+            (Mnemonic::Esc, _, [_, M]) => (8, 1),
+            (Mnemonic::Esc, _, _) => (2, 0),
+
+            (Mnemonic::Xlat, _, _) => (11, 0),
+            (Mnemonic::Lahf | Mnemonic::Sahf, _, _) => (4, 0),
+            (Mnemonic::Pushf, _, _) => (10, 0),
+            (Mnemonic::Popf, _, _) => (8, 0),
+            (Mnemonic::Aaa | Mnemonic::Aas | Mnemonic::Daa | Mnemonic::Das, _, _) => (4, 0),
+            (Mnemonic::Aam, _, _) => (83, 0),
+            (Mnemonic::Aad, _, _) => (60, 0),
+            (Mnemonic::Cbw, _, _) => (2, 0),
+            (Mnemonic::Cwd, _, _) => (5, 0),
+            (Mnemonic::Nop | Mnemonic::Wait, _, _) => (3, 0),
+            (
+                Mnemonic::Clc
+                | Mnemonic::Cmc
+                | Mnemonic::Stc
+                | Mnemonic::Cld
+                | Mnemonic::Std
+                | Mnemonic::Cli
+                | Mnemonic::Sti
+                | Mnemonic::Hlt,
+                _,
+                _,
+            ) => (2, 0),
+            _ => return None,
+        },
+    )
+}
+
+/// Estimates the clocks `instruction` took to go from `before` to `after` on `cpu`. The
+/// states settle whether a branch was taken and how many times a REP prefix repeated.
+/// `None` for instructions the manual gives no timing for.
+pub fn estimate(
+    instruction: &Instruction,
+    cpu: Cpu,
+    before: &Snapshot,
+    after: &Snapshot,
+) -> Option<Clocks> {
+    let (base, transfers) = base_clocks(instruction, before, after)?;
+    let address = instruction
+        .operands
+        .iter()
+        .find_map(|operand| match operand {
+            Operand::Memory(address) => Some(address),
+            _ => None,
+        });
+    // The accumulator MOVs take their address straight from the instruction
+    let effective_address = match address {
+        Some(address) if !instruction.accumulator_direct => {
+            effective_address_clocks(instruction, address)
+        }
+        _ => 0,
+    };
+
+    // Far pointers and LDS/LES move two words whatever the W bit says
+    let words = instruction.width == Some(Width::Word)
+        || instruction.far
+        || matches!(instruction.mnemonic, Mnemonic::Lds | Mnemonic::Les);
+    let split = words
+        && match (cpu, address) {
+            (Cpu::I8088, _) => true,
+            (Cpu::I8086, Some(address)) => effective_offset(&before.registers, address) % 2 == 1,
+            (Cpu::I8086, None) => false,
+        };
+    let lock = if instruction.prefixes.lock { 2 } else { 0 };
+    Some(Clocks {
+        base: base + lock,
+        effective_address,
+        penalty: if split { 4 * transfers } else { 0 },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode;
+    use crate::simulator::Simulator;
+
+    /// Runs `program` and returns the clocks of its last instruction.
+    fn last_clocks(program: &[u8], cpu: Cpu) -> Clocks {
+        let mut simulator = Simulator::new();
+        simulator.load(program);
+        let mut clocks = None;
+        loop {
+            let before = simulator.snapshot();
+            match simulator.step() {
+                Some(result) => {
+                    let instruction = result.unwrap();
+                    clocks = estimate(&instruction, cpu, &before, &simulator.snapshot());
+                }
+                None => return clocks.unwrap(),
+            }
+        }
+    }
+
+    #[test]
+    fn odd_word_addresses_cost_extra_on_the_8086_only() {
+        // mov bx, 1001; add dx, [bx]
+        let program = [0xbb, 0xe9, 0x03, 0x03, 0x17];
+        let expected = Clocks {
+            base: 9,
+            effective_address: 5,
+            penalty: 4,
+        };
+        assert_eq!(last_clocks(&program, Cpu::I8086), expected);
+        assert_eq!(last_clocks(&program, Cpu::I8088), expected);
+        // mov bx, 1000; add dx, [bx]
+        let program = [0xbb, 0xe8, 0x03, 0x03, 0x17];
+        assert_eq!(last_clocks(&program, Cpu::I8086).penalty, 0);
+        assert_eq!(last_clocks(&program, Cpu::I8088).penalty, 4);
+        assert_eq!(last_clocks(&program, Cpu::I8086).to_string(), " (9 + 5ea)");
+    }
+
+    #[test]
+    fn bp_with_zero_displacement_is_base_only() {
+        // mov cx, [bp]
+        let instruction = &decode(&[0x8b, 0x4e, 0x00]).unwrap()[0];
+        let state = Snapshot::default();
+        let clocks = estimate(instruction, Cpu::I8086, &state, &state).unwrap();
+        assert_eq!(clocks.effective_address, 5);
+        assert_eq!(clocks.total(), 13);
+    }
+    #[test]
+    fn accumulator_direct_mov_has_no_effective_address() {
+        let state = Snapshot::default();
+        // mov ax, [1000] in its A1 form
+        let instruction = &decode(&[0xa1, 0xe8, 0x03]).unwrap()[0];
+        let clocks = estimate(instruction, Cpu::I8086, &state, &state).unwrap();
+        assert_eq!((clocks.total(), clocks.effective_address), (10, 0));
+        // The same instruction through ModRM is an ordinary load
+        let instruction = &decode(&[0x8b, 0x06, 0xe8, 0x03]).unwrap()[0];
+        let clocks = estimate(instruction, Cpu::I8086, &state, &state).unwrap();
+        assert_eq!((clocks.total(), clocks.effective_address), (14, 6));
+    }
+}
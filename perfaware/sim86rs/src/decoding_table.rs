@@ -1,136 +1,615 @@
-use log::{debug, error, info};
+use log::debug;
 
-use crate::decoders::{
-    decode_far_proc_label, decode_immed16, decode_immed8, decode_immediate_to_register,
-    decode_immediate_to_register_memory, decode_jump, decode_memory_to_fro_accumulator,
-    decode_near_proc_label, decode_register, decode_register_memory_to_from_register,
-    decode_repeat, decode_segment_register,
+use crate::error::{DecodeError, DecodeErrorKind};
+use crate::instruction::{
+    EffectiveAddress, EffectiveAddressBase, Instruction, Mnemonic, Operand, Prefixes, Repeat, Width,
 };
+use crate::instruction_table::{Encoding, Field, INSTRUCTION_TABLE};
+use crate::registers::{Reg8, Register, SegReg};
+use crate::source::ByteSource;
 
-pub fn decode_first_byte(byte: u8, iterator: &mut std::slice::Iter<u8>) {
+/// Reads the instruction bytes ahead of the source's current position, without consuming
+/// them, so every table entry can start over from the first byte.
+struct Reader<'a> {
+    source: &'a mut dyn ByteSource,
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn next_byte(&mut self) -> Result<u8, DecodeErrorKind> {
+        let byte = self
+            .source
+            .peek(self.position)
+            .ok_or(DecodeErrorKind::Truncated)?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    /// A byte, sign-extended if asked to, or a little-endian word.
+    fn value(&mut self, wide: bool, sign_extend: bool) -> Result<u16, DecodeErrorKind> {
+        let lo = self.next_byte()?;
+        if wide {
+            let hi = self.next_byte()?;
+            Ok((hi as u16) << 8 | lo as u16)
+        } else if sign_extend {
+            Ok(lo as i8 as u16)
+        } else {
+            Ok(lo as u16)
+        }
+    }
+}
+
+/// Field values collected while walking an encoding. `None` means the encoding has no such
+/// field, explicit or implied.
+#[derive(Debug, Default)]
+struct Fields {
+    d: u8,
+    s: u8,
+    w: Option<u8>,
+    v: Option<u8>,
+    z: Option<u8>,
+    mod_field: Option<u8>,
+    reg: Option<u8>,
+    rm: u8,
+    sr: Option<u8>,
+    esc: Option<u8>,
+    disp: bool,
+    addr: bool,
+    data: bool,
+    data_if_w: bool,
+    rm_reg_always_w: bool,
+    relative: bool,
+    far: bool,
+    memory_only: bool,
+}
+
+/// The memory operand that MOD (other than 11) and R/M select, given the displacement bytes
+/// as read. How the displacement is interpreted depends on MOD alone, never on the opcode:
+/// a byte has already been sign-extended, a word is signed, and the direct address of
+/// MOD 00 R/M 110 is an unsigned offset.
+fn effective_address(mod_field: u8, rm: u8, disp: u16) -> EffectiveAddress {
+    match (mod_field, rm) {
+        (0b00, 0b110) => EffectiveAddress {
+            base: None,
+            displacement: Some(disp as i32),
+        },
+        (0b00, _) => EffectiveAddress {
+            base: Some(EffectiveAddressBase::from_rm_field(rm)),
+            displacement: None,
+        },
+        _ => EffectiveAddress {
+            base: Some(EffectiveAddressBase::from_rm_field(rm)),
+            displacement: Some(disp as i16 as i32),
+        },
+    }
+}
+
+enum Attempt {
+    Decoded(Instruction),
+    /// The bytes do not match the encoding; `position` is how many bytes were read before
+    /// it became clear.
+    Mismatch {
+        position: usize,
+    },
+}
+
+/// Tries to decode the bytes in `reader` as `encoding`.
+fn try_decode(encoding: &Encoding, reader: &mut Reader) -> Result<Attempt, DecodeErrorKind> {
+    let mut fields = Fields::default();
+    let mut pending = 0u8;
+    let mut pending_count = 0;
+    for field in encoding.fields {
+        let count = match field {
+            Field::Literal { count, .. } => *count,
+            Field::D | Field::S | Field::W | Field::V | Field::Z => 1,
+            Field::Mod | Field::Sr => 2,
+            Field::Reg | Field::Rm | Field::Esc(_) => 3,
+            Field::Any(count) => *count,
+            _ => 0,
+        };
+        let mut bits = 0;
+        if count != 0 {
+            if pending_count == 0 {
+                pending = reader.next_byte()?;
+                pending_count = 8;
+            }
+            // No 8086 field straddles a byte boundary
+            pending_count -= count;
+            bits = (pending >> pending_count) & (0xFF >> (8 - count));
+        }
+
+        match *field {
+            Field::Literal { value, .. } => {
+                if bits != value {
+                    return Ok(Attempt::Mismatch {
+                        position: reader.position,
+                    });
+                }
+            }
+            Field::D => fields.d = bits,
+            Field::S => fields.s = bits,
+            Field::W => fields.w = Some(bits),
+            Field::V => fields.v = Some(bits),
+            Field::Z => fields.z = Some(bits),
+            Field::Mod => fields.mod_field = Some(bits),
+            Field::Reg => fields.reg = Some(bits),
+            Field::Rm => fields.rm = bits,
+            Field::Sr => fields.sr = Some(bits),
+            Field::Esc(shift) => fields.esc = Some(fields.esc.unwrap_or(0) | bits << shift),
+            Field::Any(_) => {}
+            Field::ImpW(value) => fields.w = Some(value),
+            Field::ImpReg(value) => fields.reg = Some(value),
+            Field::ImpMod(value) => fields.mod_field = Some(value),
+            Field::ImpRm(value) => fields.rm = value,
+            Field::ImpD(value) => fields.d = value,
+            Field::ImpS(value) => fields.s = value,
+            Field::Disp => fields.disp = true,
+            Field::Addr => fields.addr = true,
+            Field::Data => fields.data = true,
+            Field::DataIfW => fields.data_if_w = true,
+            Field::RmRegAlwaysW => fields.rm_reg_always_w = true,
+            Field::RelJmpDisp => fields.relative = true,
+            Field::Far => fields.far = true,
+            Field::MemoryOnly => fields.memory_only = true,
+        }
+    }
+    debug!("  {}: {:?}", encoding.mnemonic, fields);
+
+    if fields.memory_only && fields.mod_field == Some(0b11) {
+        return Ok(Attempt::Mismatch {
+            position: reader.position,
+        });
+    }
+
+    // MOD decides whether there is a displacement and how wide it is; a direct address is
+    // always a word
+    let w = fields.w.unwrap_or(0);
+    let direct = fields.mod_field == Some(0b00) && fields.rm == 0b110;
+    let has_disp =
+        fields.disp || fields.addr || matches!(fields.mod_field, Some(0b01 | 0b10)) || direct;
+    let disp_is_wide = fields.addr || fields.mod_field == Some(0b10) || direct;
+    let disp = if has_disp {
+        reader.value(disp_is_wide, true)?
+    } else {
+        0
+    };
+    let data_is_wide = fields.data_if_w && fields.s == 0 && w == 1;
+    let data = if fields.data {
+        reader.value(data_is_wide, fields.s == 1)?
+    } else {
+        0
+    };
+    debug!("    disp: {:#x} data: {:#x}", disp, data);
+
+    let (reg_slot, mod_slot) = if fields.d == 1 { (0, 1) } else { (1, 0) };
+    let mut operands = [None, None];
+    if let Some(sr) = fields.sr {
+        operands[reg_slot] = Some(Operand::Register(Register::Segment(SegReg::from_field(sr))));
+    }
+    if let Some(reg) = fields.reg {
+        operands[reg_slot] = Some(Operand::Register(Register::from_field(reg, w)));
+    }
+    if let Some(mod_field) = fields.mod_field {
+        operands[mod_slot] = Some(if mod_field == 0b11 {
+            let w = if fields.rm_reg_always_w { 1 } else { w };
+            Operand::Register(Register::from_field(fields.rm, w))
+        } else {
+            Operand::Memory(effective_address(mod_field, fields.rm, disp))
+        });
+    }
+
+    if fields.data && has_disp && fields.mod_field.is_none() {
+        operands[0] = Some(Operand::Far {
+            segment: data,
+            offset: disp,
+        });
+    } else {
+        // Immediates go in whichever slot REG and MOD left free; OUT has one as destination
+        let last = if operands[0].is_some() { 1 } else { 0 };
+        if fields.relative {
+            operands[last] = Some(Operand::Relative(disp as i16));
+        } else if fields.data {
+            let value = if data_is_wide || fields.s == 0 {
+                data as i32
+            } else {
+                data as i16 as i32
+            };
+            operands[last] = Some(Operand::Immediate(value));
+        } else if let Some(esc) = fields.esc {
+            operands[last] = Some(Operand::Immediate(esc as i32));
+        } else if let Some(v) = fields.v {
+            operands[last] = Some(if v == 1 {
+                Operand::Register(Register::Byte(Reg8::Cl))
+            } else {
+                Operand::Immediate(1)
+            });
+        }
+    }
+
+    let mut instruction =
+        Instruction::new(encoding.mnemonic, operands.into_iter().flatten().collect());
+    instruction.width = fields.w.map(Width::from_w_field);
+    instruction.far = fields.far;
+    // Only the accumulator MOVs have both a direct address and an implied MOD
+    instruction.accumulator_direct = fields.addr && fields.mod_field.is_some();
+    instruction.data_width = fields.data.then_some(if data_is_wide {
+        Width::Word
+    } else {
+        Width::Byte
+    });
+    instruction.prefixes.rep = fields
+        .z
+        .map(|z| if z == 1 { Repeat::Rep } else { Repeat::Repne });
+    Ok(Attempt::Decoded(instruction))
+}
+
+/// Decodes one instruction, table entry by table entry, folding any LOCK, REP and segment
+/// prefixes into the instruction that follows them.
+fn decode_encoding(reader: &mut Reader) -> Result<Instruction, DecodeErrorKind> {
+    let mut prefixes = Prefixes::default();
+    loop {
+        let start = reader.position;
+        let mut furthest = 0;
+        let mut decoded = None;
+        for encoding in INSTRUCTION_TABLE {
+            reader.position = start;
+            match try_decode(encoding, reader)? {
+                Attempt::Decoded(instruction) => {
+                    decoded = Some(instruction);
+                    break;
+                }
+                Attempt::Mismatch { position } => furthest = furthest.max(position - start),
+            }
+        }
+
+        let mut instruction = match decoded {
+            Some(instruction) => instruction,
+            None => {
+                reader.position = start;
+                let opcode = reader.next_byte()?;
+                // Some encoding took the opcode, so it was a later byte that did not fit
+                return Err(if furthest > 1 {
+                    DecodeErrorKind::InvalidModRm {
+                        opcode,
+                        modrm: reader.next_byte()?,
+                    }
+                } else {
+                    DecodeErrorKind::UnknownOpcode(opcode)
+                });
+            }
+        };
+        match instruction.mnemonic {
+            Mnemonic::Lock => prefixes.lock = true,
+            Mnemonic::Rep => prefixes.rep = instruction.prefixes.rep,
+            Mnemonic::Segment => match instruction.operands[..] {
+                [Operand::Register(Register::Segment(segment))] => prefixes.segment = Some(segment),
+                _ => unreachable!("segment prefixes name a segment register"),
+            },
+            _ => {
+                instruction.prefixes = prefixes;
+                return Ok(instruction);
+            }
+        }
+    }
+}
+
+/// Decodes the instruction at the current position of `source`, which is at `address`, and
+/// consumes its bytes. Returns `None` at the end of the input; on an error nothing is consumed.
+pub fn decode_next<S: ByteSource>(
+    source: &mut S,
+    address: usize,
+) -> Option<Result<Instruction, DecodeError>> {
+    let result = peek_instruction(source, address)?;
+    if let Ok(instruction) = &result {
+        source.consume(instruction.length);
+    }
+    Some(result)
+}
+
+/// Decodes the instruction at the current position of `source` without consuming it.
+fn peek_instruction<S: ByteSource>(
+    source: &mut S,
+    address: usize,
+) -> Option<Result<Instruction, DecodeError>> {
+    let byte = source.peek(0)?;
     debug!("First Byte: 0b{:08b} 0x{:02x}", byte, byte);
-    match byte {
-        0x0..=0x3 => decode_register_memory_to_from_register("ADD", byte, iterator),
-        0x4..=0x5 => decode_memory_to_fro_accumulator("ADD", byte, iterator, false),
-        0x6 => decode_segment_register("PUSH", byte),
-        0x8..=0x0B => decode_register_memory_to_from_register("OR", byte, iterator),
-        0x0C..=0x0D => decode_memory_to_fro_accumulator("OR", byte, iterator, false),
-        0x0E => decode_segment_register("PUSH", byte),
-        0x10..=0x13 => decode_register_memory_to_from_register("ADC", byte, iterator),
-        0x14..=0x15 => decode_memory_to_fro_accumulator("ADC", byte, iterator, false),
-        0x18..=0x1B => decode_register_memory_to_from_register("SBB", byte, iterator),
-        0x1C..=0x1D => decode_memory_to_fro_accumulator("SBB", byte, iterator, false),
-        0x1F => decode_segment_register("POP", byte),
-        0x20..=0x23 => decode_register_memory_to_from_register("AND", byte, iterator),
-        0x24..=0x25 => decode_memory_to_fro_accumulator("AND", byte, iterator, false),
-        0x26 => info!("ES"),
-        0x27 => info!("DAA"),
-        0x28..=0x2B => decode_register_memory_to_from_register("SUB", byte, iterator),
-        0x2C..=0x2D => decode_memory_to_fro_accumulator("SUB", byte, iterator, false),
-        0x2E => info!("CS"),
-        0x2F => info!("DAS"),
-        0x30..=0x33 => decode_register_memory_to_from_register("XOR", byte, iterator),
-        0x34..=0x35 => decode_memory_to_fro_accumulator("XOR", byte, iterator, false),
-        0x36 => info!("SS"),
-        0x37 => info!("AAA"),
-        0x38..=0x3B => decode_register_memory_to_from_register("CMP", byte, iterator),
-        0x3C..=0x3D => decode_memory_to_fro_accumulator("CMP", byte, iterator, false),
-        0x3E => info!("DS"),
-        0x3F => info!("AAS"),
-        0x40..=0x47 => decode_register("INC", byte),
-        0x48..=0x4F => decode_register("DEC", byte),
-        0x50..=0x57 => decode_register("PUSH", byte),
-        0x58..=0x5F => decode_register("POP", byte),
-        0x70 => decode_jump("JO", byte, iterator),
-        0x71 => decode_jump("JNO", byte, iterator),
-        0x72 => decode_jump("JB", byte, iterator),
-        0x73 => decode_jump("JAE", byte, iterator),
-        0x74 => decode_jump("JZ", byte, iterator),
-        0x75 => decode_jump("JNZ", byte, iterator),
-        0x76 => decode_jump("JBE", byte, iterator),
-        0x77 => decode_jump("JA", byte, iterator),
-        0x78 => decode_jump("JS", byte, iterator),
-        0x79 => decode_jump("JNS", byte, iterator),
-        0x7A => decode_jump("JP", byte, iterator),
-        0x7B => decode_jump("JNP", byte, iterator),
-        0x7C => decode_jump("JL", byte, iterator),
-        0x7D => decode_jump("JGE", byte, iterator),
-        0x7E => decode_jump("JLE", byte, iterator),
-        0x7F => decode_jump("JG", byte, iterator),
-        0x80..=0x83 => decode_immediate_to_register_memory("ADD", byte, iterator),
-        0x84..=0x85 => decode_register_memory_to_from_register("TEST", byte, iterator),
-        0x86 => decode_register_memory_to_from_register("XCHG", byte, iterator),
-        0x87 => decode_register_memory_to_from_register("XCHG", byte, iterator),
-        0x88..=0x8B => decode_register_memory_to_from_register("MOV", byte, iterator),
-        0x8C => decode_register_memory_to_from_register("MOV", byte, iterator),
-        0x8D => decode_register_memory_to_from_register("LEA", byte, iterator),
-        0x8F => decode_register_memory_to_from_register("POP", byte, iterator),
-        0x90 => info!("NOP"),
-        0x92 => info!("XCHG AX, DX"),
-        0x94 => info!("XCHG AX, SP"),
-        0x96 => info!("XCHG AX, SI"),
-        0x97 => info!("XCHG AX, DI"),
-        0x98 => info!("CBW"),
-        0x99 => info!("CWD"),
-        0x9A => decode_far_proc_label("CALL", byte, iterator),
-        0x9B => info!("WAIT"),
-        0x9C => info!("PUSHF"),
-        0x9D => info!("POPF"),
-        0x9E => info!("SAHF"),
-        0x9F => info!("LAHF"),
-        0xA0..=0xA1 => decode_memory_to_fro_accumulator("MOV", byte, iterator, false),
-        0xA2..=0xA3 => decode_memory_to_fro_accumulator("MOV", byte, iterator, true),
-        0xA8..=0xA9 => decode_memory_to_fro_accumulator("TEST", byte, iterator, false),
-        0xB0..=0xBF => decode_immediate_to_register("MOV", byte, iterator),
-        0xC2 => decode_immediate_to_register("RET", byte, iterator),
-        0xC3 => info!("RET"),
-        0xC4 => decode_register_memory_to_from_register("LES", byte, iterator),
-        0xC5 => decode_register_memory_to_from_register("LDS", byte, iterator),
-        0xC6..=0xC7 => decode_immediate_to_register_memory("MOV", byte, iterator),
-        0xCA => decode_immed16("RETF", byte, iterator),
-        0xCB => info!("RETF"),
-        0xCC => info!("INT3"),
-        0xCD => decode_immed8("INT", byte, iterator),
-        0xCE => info!("INTO"),
-        0xCF => info!("IRET"),
-        0xD0..=0xD3 => decode_register_memory_to_from_register("SHL", byte, iterator),
-        0xD4 => {
-            info!("AAM");
-            iterator.next();
+    let mut reader = Reader {
+        source,
+        position: 0,
+    };
+    Some(
+        decode_encoding(&mut reader)
+            .map(|mut instruction| {
+                instruction.address = address;
+                instruction.length = reader.position;
+                instruction
+            })
+            .map_err(|kind| DecodeError {
+                offset: address,
+                kind,
+            }),
+    )
+}
+
+/// Decodes instructions back to back from a byte source, numbering them from address 0.
+///
+/// An error ends the iteration, so plain loops over arbitrary input always finish. To go on
+/// past the offending byte instead, step over it with [`Decoder::skip_byte`], which resumes
+/// decoding after it.
+#[derive(Debug)]
+pub struct Decoder<S> {
+    source: S,
+    address: usize,
+    bytes: Vec<u8>,
+    failed: bool,
+}
+
+impl<S: ByteSource> Decoder<S> {
+    pub fn new(source: S) -> Decoder<S> {
+        Decoder::at(source, 0)
+    }
+
+    /// Starts numbering instructions at `address` instead of 0.
+    pub fn at(source: S, address: usize) -> Decoder<S> {
+        Decoder {
+            source,
+            address,
+            bytes: Vec::new(),
+            failed: false,
         }
-        0xD5 => {
-            info!("AAD");
-            iterator.next();
+    }
+
+    /// Address of the next instruction.
+    pub fn address(&self) -> usize {
+        self.address
+    }
+
+    /// The raw bytes of the instruction last returned, prefixes included, or of the byte
+    /// last skipped.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Consumes and returns one byte without decoding it, and resumes decoding after an
+    /// error.
+    pub fn skip_byte(&mut self) -> Option<u8> {
+        let byte = self.source.peek(0)?;
+        self.failed = false;
+        self.source.consume(1);
+        self.address += 1;
+        self.bytes = vec![byte];
+        Some(byte)
+    }
+
+    pub fn into_source(self) -> S {
+        self.source
+    }
+}
+
+impl<S: ByteSource> Iterator for Decoder<S> {
+    type Item = Result<Instruction, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let result = peek_instruction(&mut self.source, self.address)?;
+        match &result {
+            Ok(instruction) => {
+                self.bytes = (0..instruction.length)
+                    .filter_map(|index| self.source.peek(index))
+                    .collect();
+                self.source.consume(instruction.length);
+                self.address += instruction.length;
+            }
+            Err(_) => self.failed = true,
         }
-        0xD7 => info!("XLAT"),
-        0xE0 => decode_jump("LOOPNZ", byte, iterator),
-        0xE1 => decode_jump("LOOPZ", byte, iterator),
-        0xE2 => decode_jump("LOOP", byte, iterator),
-        0xE3 => decode_jump("JCXZ", byte, iterator),
-        0xE4..=0xE5 => decode_memory_to_fro_accumulator("IN", byte, iterator, false),
-        0xE8 => decode_near_proc_label("CALL", byte, iterator),
-        0xE9 => decode_near_proc_label("JMP", byte, iterator),
-        0xEA => decode_far_proc_label("JMP", byte, iterator),
-        0xEC => info!("IN AL, DX"),
-        0xED => info!("IN AX, DX"),
-        0xEE => info!("OUT DX, AL"),
-        0xE6..=0xE7 => decode_memory_to_fro_accumulator("OUT", byte, iterator, true),
-        0xF0 => info!("LOCK"),
-        0xF3 => decode_repeat("REP", byte, iterator),
-        0xF4 => info!("HLT"),
-        0xF5 => info!("CMC"),
-        0xF6..=0xF7 => decode_register_memory_to_from_register("NEG", byte, iterator),
-        0xF8 => info!("CLC"),
-        0xF9 => info!("STC"),
-        0xFA => info!("CLI"),
-        0xFB => info!("STI"),
-        0xFC => info!("CLD"),
-        0xFD => info!("STD"),
-        0xFE => decode_register_memory_to_from_register("INC", byte, iterator),
-        0xFF => decode_register_memory_to_from_register("PUSH", byte, iterator),
-        _ => {
-            error!("Unknown opcode: 0b{:08b} 0x{:02x}", byte, byte);
-            std::process::exit(1);
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether the 8086 leaves `opcode` followed by `modrm` undefined, so the decoder
+    /// rejects it with `InvalidModRm`. Prefixes pass the question on to what follows them.
+    fn documented_invalid(opcode: u8, modrm: u8) -> Option<(u8, u8)> {
+        let mod_field = modrm >> 6;
+        let reg = modrm >> 3 & 0b111;
+        match opcode {
+            0x26 | 0x2e | 0x36 | 0x3e | 0xf0..=0xf3 => documented_invalid(modrm, 0x00),
+            // LEA, LES and LDS need a memory operand
+            0x8d | 0xc4 | 0xc5 if mod_field == 0b11 => Some((opcode, modrm)),
+            // Undocumented SETMO
+            0xd0..=0xd3 if reg == 0b110 => Some((opcode, modrm)),
+            // AAM and AAD in any base but 10
+            0xd4 | 0xd5 if modrm != 0x0a => Some((opcode, modrm)),
+            // The FF operations on a byte operand
+            0xfe if reg >= 0b010 => Some((opcode, modrm)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn every_opcode_and_modrm_decodes() {
+        for opcode in 0..=0xFF_u8 {
+            for modrm in 0..=0xFF_u8 {
+                let bytes = [opcode, modrm, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+                let result = decode_next(&mut &bytes[..], 0).unwrap();
+                match (result, documented_invalid(opcode, modrm)) {
+                    (Ok(_), None) => {}
+                    (Err(error), Some((opcode, modrm))) => {
+                        assert_eq!(error.kind, DecodeErrorKind::InvalidModRm { opcode, modrm })
+                    }
+                    (result, _) => {
+                        panic!("{:02x} {:02x} decoded as {:?}", opcode, modrm, result)
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn iteration_ends_at_an_error_until_the_byte_is_skipped() {
+        // nop; lea with a register operand; nop
+        let bytes = [0x90, 0x8d, 0xc3, 0x90];
+        let results: Vec<_> = Decoder::new(&bytes[..]).collect();
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok() && results[1].is_err());
+        assert!(Decoder::new(&bytes[..]).filter_map(Result::ok).count() == 1);
+
+        let mut decoder = Decoder::new(&bytes[..]);
+        decoder.nth(1).unwrap().unwrap_err();
+        assert_eq!(decoder.next(), None);
+        assert_eq!(decoder.skip_byte(), Some(0x8d));
+        // 0xc3 is RET, then the last nop
+        assert_eq!(decoder.count(), 2);
+    }
+
+    #[test]
+    fn unused_reg_values_alias_the_documented_instruction() {
+        // pop [bx] with REG = 5; mov byte [bx], 7 with REG = 3; push [bx] as FF /7;
+        // test byte [bx], 7 as F6 /1; mov ax, es with the third segment bit set
+        let bytes = [
+            0x8f, 0x2f, 0xc6, 0x1f, 0x07, 0xff, 0x3f, 0xf6, 0x0f, 0x07, 0x8c, 0xe0,
+        ];
+        let texts: Vec<String> = Decoder::new(&bytes[..])
+            .map(|instruction| instruction.unwrap().to_string())
+            .collect();
+        assert_eq!(
+            texts,
+            [
+                "POP WORD [BX]",
+                "MOV BYTE [BX], 7",
+                "PUSH WORD [BX]",
+                "TEST BYTE [BX], 7",
+                "MOV AX, ES",
+            ]
+        );
+    }
+
+    /// Opcodes that take a ModRM byte, with the REG field they need (if it selects the
+    /// operation) and how many immediate bytes follow the displacement.
+    const MODRM_FAMILIES: &[(&str, u8, Option<u8>, usize)] = &[
+        ("mov r8, r/m8", 0x8a, None, 0),
+        ("mov r/m16, r16", 0x89, None, 0),
+        ("mov r/m16, sr", 0x8c, Some(0b011), 0),
+        ("mov r/m8, imm8", 0xc6, Some(0b000), 1),
+        ("mov r/m16, imm16", 0xc7, Some(0b000), 2),
+        ("add r16, r/m16", 0x03, None, 0),
+        ("add r/m16, imm8", 0x83, Some(0b000), 1),
+        ("cmp r/m16, imm16", 0x81, Some(0b111), 2),
+        ("test r/m16, r16", 0x85, None, 0),
+        ("xchg r/m16, r16", 0x87, None, 0),
+        ("lea", 0x8d, None, 0),
+        ("lds", 0xc5, None, 0),
+        ("les", 0xc4, None, 0),
+        ("push r/m16", 0xff, Some(0b110), 0),
+        ("pop r/m16", 0x8f, Some(0b000), 0),
+        ("inc r/m8", 0xfe, Some(0b000), 0),
+        ("call r/m16", 0xff, Some(0b010), 0),
+        ("call far", 0xff, Some(0b011), 0),
+        ("jmp r/m16", 0xff, Some(0b100), 0),
+        ("mul r/m16", 0xf7, Some(0b100), 0),
+        ("shl r/m16, 1", 0xd1, Some(0b100), 0),
+        ("esc", 0xd8, None, 0),
+    ];
+
+    #[test]
+    fn displacements_depend_only_on_mod_and_rm() {
+        use EffectiveAddressBase::*;
+        const BASES: [EffectiveAddressBase; 8] = [BxSi, BxDi, BpSi, BpDi, Si, Di, Bp, Bx];
+        const DISP8: &[u8] = &[0x00, 0x01, 0x7f, 0x80, 0xfe, 0xff];
+        const DISP16: &[u16] = &[0x0000, 0x0001, 0x1234, 0x7fff, 0x8000, 0xfffe, 0xffff];
+
+        for &(name, opcode, reg, data) in MODRM_FAMILIES {
+            for modrm in 0..=0xbf_u8 {
+                let (mod_field, rm) = (modrm >> 6, modrm & 0b111);
+                if reg.is_some_and(|reg| reg != (modrm >> 3) & 0b111) {
+                    continue;
+                }
+
+                // Every displacement encoding this MOD and R/M can carry, and the operand
+                // it must decode to
+                let cases: Vec<(Vec<u8>, EffectiveAddress)> = match (mod_field, rm) {
+                    (0b00, 0b110) => DISP16
+                        .iter()
+                        .map(|&disp| {
+                            let address = EffectiveAddress {
+                                base: None,
+                                displacement: Some(disp as i32),
+                            };
+                            (disp.to_le_bytes().to_vec(), address)
+                        })
+                        .collect(),
+                    (0b00, _) => vec![(
+                        vec![],
+                        EffectiveAddress {
+                            base: Some(BASES[rm as usize]),
+                            displacement: None,
+                        },
+                    )],
+                    (0b01, _) => DISP8
+                        .iter()
+                        .map(|&disp| {
+                            let address = EffectiveAddress {
+                                base: Some(BASES[rm as usize]),
+                                displacement: Some(disp as i8 as i32),
+                            };
+                            (vec![disp], address)
+                        })
+                        .collect(),
+                    _ => DISP16
+                        .iter()
+                        .map(|&disp| {
+                            let address = EffectiveAddress {
+                                base: Some(BASES[rm as usize]),
+                                displacement: Some(disp as i16 as i32),
+                            };
+                            (disp.to_le_bytes().to_vec(), address)
+                        })
+                        .collect(),
+                };
+
+                for (disp, expected) in cases {
+                    let mut bytes = vec![opcode, modrm];
+                    bytes.extend(&disp);
+                    bytes.extend(std::iter::repeat_n(0x5a, data));
+                    let instruction = decode_next(&mut &bytes[..], 0)
+                        .unwrap()
+                        .unwrap_or_else(|error| panic!("{} {:02x?}: {}", name, bytes, error));
+                    assert_eq!(instruction.length, bytes.len(), "{} {:02x?}", name, bytes);
+                    let memory: Vec<_> = instruction
+                        .operands
+                        .iter()
+                        .filter_map(|operand| match operand {
+                            Operand::Memory(address) => Some(*address),
+                            _ => None,
+                        })
+                        .collect();
+                    assert_eq!(memory, [expected], "{} {:02x?}", name, bytes);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn displacements_print_signed_and_direct_addresses_unsigned() {
+        let cases: &[(&[u8], &str)] = &[
+            (&[0x8b, 0x46, 0xff], "MOV AX, [BP - 1]"),
+            (&[0x8b, 0x46, 0x80], "MOV AX, [BP - 128]"),
+            (&[0x8b, 0x46, 0x7f], "MOV AX, [BP + 127]"),
+            (&[0x8b, 0x86, 0x00, 0x80], "MOV AX, [BP - 32768]"),
+            (&[0x8b, 0x86, 0xff, 0x7f], "MOV AX, [BP + 32767]"),
+            (&[0xff, 0x77, 0xfe], "PUSH WORD [BX - 2]"),
+            (&[0x8d, 0x87, 0x00, 0xff], "LEA AX, [BX - 256]"),
+            (&[0x8b, 0x06, 0x00, 0x80], "MOV AX, [32768]"),
+            (&[0x8b, 0x06, 0xff, 0xff], "MOV AX, [65535]"),
+        ];
+        for (bytes, text) in cases {
+            let instruction = decode_next(&mut &bytes[..], 0).unwrap().unwrap();
+            assert_eq!(instruction.to_string(), *text);
         }
     }
 }
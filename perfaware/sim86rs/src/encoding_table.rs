@@ -0,0 +1,446 @@
+//! The reverse of `decoding_table`: finds the bytes for an instruction by walking the same
+//! table. Every candidate encoding is decoded again and only kept if it reads back as the
+//! instruction asked for, so the two directions cannot drift apart.
+
+use crate::decoding_table::decode_next;
+use crate::error::EncodeError;
+use crate::instruction::{Instruction, Mnemonic, Operand, Repeat, Width};
+use crate::instruction_table::{Encoding, Field, INSTRUCTION_TABLE};
+use crate::registers::{Reg8, Register};
+
+/// What an encoding spells out and what it leaves implied, gathered from its fields.
+#[derive(Debug, Default)]
+struct Shape {
+    d: Option<u8>,
+    s: Option<u8>,
+    w: Option<u8>,
+    v: bool,
+    mod_field: Option<u8>,
+    rm: Option<u8>,
+    explicit_mod: bool,
+    reg: bool,
+    sr: bool,
+    esc: bool,
+    disp: bool,
+    addr: bool,
+    data: bool,
+    data_if_w: bool,
+    relative: bool,
+    prefix: bool,
+}
+
+impl Shape {
+    /// `None` in `d`, `s` and `w` means the bit is explicit and free to choose.
+    fn of(encoding: &Encoding) -> Shape {
+        let mut shape = Shape {
+            d: Some(0),
+            s: Some(0),
+            w: Some(0),
+            prefix: matches!(
+                encoding.mnemonic,
+                Mnemonic::Lock | Mnemonic::Rep | Mnemonic::Segment
+            ),
+            ..Shape::default()
+        };
+        for field in encoding.fields {
+            match *field {
+                Field::D => shape.d = None,
+                Field::S => shape.s = None,
+                Field::W => shape.w = None,
+                Field::V => shape.v = true,
+                Field::Mod => shape.explicit_mod = true,
+                Field::Reg | Field::ImpReg(_) => shape.reg = true,
+                Field::Sr => shape.sr = true,
+                Field::Esc(_) => shape.esc = true,
+                Field::ImpD(value) => shape.d = Some(value),
+                Field::ImpS(value) => shape.s = Some(value),
+                Field::ImpW(value) => shape.w = Some(value),
+                Field::ImpMod(value) => shape.mod_field = Some(value),
+                Field::ImpRm(value) => shape.rm = Some(value),
+                Field::Disp => shape.disp = true,
+                Field::Addr => shape.addr = true,
+                Field::Data => shape.data = true,
+                Field::DataIfW => shape.data_if_w = true,
+                Field::RelJmpDisp => shape.relative = true,
+                _ => {}
+            }
+        }
+        shape
+    }
+
+    fn has_mod(&self) -> bool {
+        self.explicit_mod || self.mod_field.is_some()
+    }
+}
+
+/// Where an operand lands in an encoding, in the order `decoding_table` fills the slots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Reg,
+    Rm,
+    Far,
+    Extra,
+}
+
+/// Values for the bit fields and trailing bytes of one candidate encoding.
+#[derive(Debug, Default, Clone, Copy)]
+struct Choice {
+    d: u8,
+    s: u8,
+    w: u8,
+    v: u8,
+    mod_field: u8,
+    reg: u8,
+    rm: u8,
+    sr: u8,
+    esc: u8,
+    disp: u16,
+    data: u16,
+}
+
+/// Fills in MOD, R/M and the displacement for a register or memory operand.
+fn place_rm(operand: &Operand, choice: &mut Choice) -> Option<()> {
+    match operand {
+        Operand::Register(Register::Byte(register)) => {
+            choice.mod_field = 0b11;
+            choice.rm = register.field();
+        }
+        Operand::Register(Register::Word(register)) => {
+            choice.mod_field = 0b11;
+            choice.rm = register.field();
+        }
+        Operand::Memory(address) => {
+            let displacement = address.displacement.unwrap_or(0);
+            choice.disp = displacement as u16;
+            match address.base {
+                None => {
+                    choice.mod_field = 0b00;
+                    choice.rm = 0b110;
+                }
+                Some(base) => {
+                    choice.rm = base.rm_field();
+                    // MOD 00 with R/M 110 is a direct address, so [BP] needs a zero byte
+                    choice.mod_field = if displacement == 0 && choice.rm != 0b110 {
+                        0b00
+                    } else if i8::try_from(displacement).is_ok() {
+                        0b01
+                    } else {
+                        0b10
+                    };
+                }
+            }
+        }
+        _ => return None,
+    }
+    Some(())
+}
+
+/// Lays out `encoding` with the bits in `choice`, `prefix_length` bytes into the
+/// instruction. Relative displacements are worked out here, once the length is known.
+fn emit(
+    encoding: &Encoding,
+    shape: &Shape,
+    mut choice: Choice,
+    address: usize,
+    prefix_length: usize,
+    branch_target: Option<u16>,
+) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut pending = 0u8;
+    let mut pending_count = 0;
+    for field in encoding.fields {
+        let (count, value) = match *field {
+            Field::Literal { count, value } => (count, value),
+            Field::D => (1, choice.d),
+            Field::S => (1, choice.s),
+            Field::W => (1, choice.w),
+            Field::V => (1, choice.v),
+            Field::Mod => (2, choice.mod_field),
+            Field::Reg => (3, choice.reg),
+            Field::Rm => (3, choice.rm),
+            Field::Sr => (2, choice.sr),
+            Field::Esc(shift) => (3, choice.esc >> shift & 0b111),
+            Field::Any(count) => (count, 0),
+            _ => (0, 0),
+        };
+        if count != 0 {
+            pending = ((pending as u16) << count) as u8 | value;
+            pending_count += count;
+            if pending_count == 8 {
+                bytes.push(pending);
+                pending = 0;
+                pending_count = 0;
+            }
+        }
+    }
+
+    let mod_field = shape
+        .explicit_mod
+        .then_some(choice.mod_field)
+        .or(shape.mod_field);
+    let rm = shape.rm.unwrap_or(choice.rm);
+    let direct = mod_field == Some(0b00) && rm == 0b110;
+    let has_disp = shape.disp || shape.addr || matches!(mod_field, Some(0b01 | 0b10)) || direct;
+    let disp_is_wide = shape.addr || mod_field == Some(0b10) || direct;
+    let data_is_wide = shape.data_if_w && choice.s == 0 && choice.w == 1;
+
+    if let (true, Some(target)) = (shape.relative, branch_target) {
+        let length = prefix_length
+            + bytes.len()
+            + if disp_is_wide { 2 } else { 1 }
+            + if shape.data {
+                1 + data_is_wide as usize
+            } else {
+                0
+            };
+        choice.disp = target.wrapping_sub((address + length) as u16);
+    }
+    if has_disp {
+        bytes.push(choice.disp as u8);
+        if disp_is_wide {
+            bytes.push((choice.disp >> 8) as u8);
+        }
+    }
+    if shape.data {
+        bytes.push(choice.data as u8);
+        if data_is_wide {
+            bytes.push((choice.data >> 8) as u8);
+        }
+    }
+    bytes
+}
+
+/// Both operands of XCHG and TEST are sources, so NASM takes them either way round.
+fn is_swappable(mnemonic: Mnemonic) -> bool {
+    matches!(mnemonic, Mnemonic::Xchg | Mnemonic::Test)
+}
+
+/// Does `got`, freshly decoded, say the same thing as `wanted`?
+fn reads_back(wanted: &Instruction, got: &Instruction, branch_target: Option<u16>) -> bool {
+    let operand_matches = |wanted: &Operand, actual: &Operand| match (wanted, actual) {
+        (Operand::Register(a), Operand::Register(b)) => a == b,
+        (Operand::Memory(a), Operand::Memory(b)) => {
+            a.base == b.base
+                && a.displacement.unwrap_or(0) as u16 == b.displacement.unwrap_or(0) as u16
+        }
+        (Operand::Immediate(a), Operand::Immediate(b)) => {
+            if got.width == Some(Width::Byte) {
+                (-128..=255).contains(a) && *a as u8 == *b as u8
+            } else {
+                (-32768..=65535).contains(a) && *a as u16 == *b as u16
+            }
+        }
+        (Operand::Relative(_), Operand::Relative(_)) => got.branch_target() == branch_target,
+        (a @ Operand::Far { .. }, b @ Operand::Far { .. }) => a == b,
+        _ => false,
+    };
+    wanted.mnemonic == got.mnemonic
+        && wanted.far == got.far
+        && wanted.prefixes == got.prefixes
+        && wanted.width.is_none_or(|width| got.width == Some(width))
+        && wanted.operands.len() == got.operands.len()
+        && wanted
+            .operands
+            .iter()
+            .zip(&got.operands)
+            .all(|(wanted, actual)| operand_matches(wanted, actual))
+}
+
+/// Every way `encoding` could spell `instruction` that decodes back to it.
+fn candidates(
+    encoding: &Encoding,
+    instruction: &Instruction,
+    prefix: &[u8],
+    branch_target: Option<u16>,
+) -> Vec<(Vec<u8>, Instruction)> {
+    let shape = Shape::of(encoding);
+    let free = |bit: Option<u8>| match bit {
+        Some(value) => value..=value,
+        None => 0..=1,
+    };
+    let mut found = Vec::new();
+    for d in free(shape.d) {
+        for s in free(shape.s) {
+            for w in free(shape.w) {
+                for v in free((!shape.v).then_some(0)) {
+                    let choice = Choice {
+                        d,
+                        s,
+                        w,
+                        v,
+                        ..Choice::default()
+                    };
+                    let Some(choice) = place_operands(&shape, choice, instruction) else {
+                        continue;
+                    };
+                    let mut bytes = prefix.to_vec();
+                    bytes.extend(emit(
+                        encoding,
+                        &shape,
+                        choice,
+                        instruction.address,
+                        prefix.len(),
+                        branch_target,
+                    ));
+                    if let Some(Ok(decoded)) = decode_next(&mut &bytes[..], instruction.address) {
+                        if decoded.length == bytes.len()
+                            && reads_back(instruction, &decoded, branch_target)
+                        {
+                            found.push((bytes, decoded));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    found
+}
+
+/// Assigns the operands of `instruction` to the fields of an encoding with `shape`, the
+/// way `decoding_table` would read them back.
+fn place_operands(shape: &Shape, mut choice: Choice, instruction: &Instruction) -> Option<Choice> {
+    let (reg_slot, mod_slot) = if choice.d == 1 { (0, 1) } else { (1, 0) };
+    let mut slots = [None, None];
+    if shape.reg || shape.sr {
+        slots[reg_slot] = Some(Role::Reg);
+    }
+    if shape.has_mod() {
+        slots[mod_slot] = Some(Role::Rm);
+    }
+    if shape.data && (shape.disp || shape.addr) && !shape.has_mod() {
+        slots[0] = Some(Role::Far);
+    } else if shape.relative || shape.data || shape.esc || shape.v {
+        let last = if slots[0].is_some() { 1 } else { 0 };
+        slots[last] = Some(Role::Extra);
+    }
+    let roles: Vec<Role> = slots.into_iter().flatten().collect();
+    if roles.len() != instruction.operands.len() {
+        return None;
+    }
+
+    for (role, operand) in roles.into_iter().zip(&instruction.operands) {
+        match (role, operand) {
+            (Role::Reg, Operand::Register(Register::Byte(register))) => {
+                choice.reg = register.field()
+            }
+            (Role::Reg, Operand::Register(Register::Word(register))) => {
+                choice.reg = register.field()
+            }
+            (Role::Reg, Operand::Register(Register::Segment(register))) => {
+                choice.sr = register.field()
+            }
+            (Role::Rm, operand) => place_rm(operand, &mut choice)?,
+            (Role::Far, Operand::Far { segment, offset }) => {
+                choice.disp = *offset;
+                choice.data = *segment;
+            }
+            (Role::Extra, Operand::Immediate(value)) if shape.esc => choice.esc = *value as u8,
+            (Role::Extra, Operand::Immediate(value)) => choice.data = *value as u16,
+            (Role::Extra, Operand::Relative(_)) => {}
+            (Role::Extra, Operand::Register(Register::Byte(Reg8::Cl))) => {}
+            _ => return None,
+        }
+    }
+    Some(choice)
+}
+
+/// The LOCK, REP/REPNE and segment override bytes `instruction` needs, in that order.
+fn prefix_bytes(instruction: &Instruction) -> Vec<u8> {
+    let prefixes = instruction.prefixes;
+    let mut bytes = Vec::new();
+    if prefixes.lock {
+        bytes.push(0xF0);
+    }
+    match prefixes.rep {
+        Some(Repeat::Rep) => bytes.push(0xF3),
+        Some(Repeat::Repne) => bytes.push(0xF2),
+        None => {}
+    }
+    if let Some(segment) = prefixes.segment {
+        bytes.push(0x26 | segment.field() << 3);
+    }
+    bytes
+}
+
+/// Encodes `instruction` at `instruction.address`, picking the shortest encoding and the
+/// first one in table order on a tie; with D free that is the D = 0 form, as NASM emits.
+/// `Relative` operands are placeholders: the displacement is taken from `branch_target`.
+pub fn encode(
+    instruction: &Instruction,
+    branch_target: Option<u16>,
+) -> Result<Vec<u8>, EncodeError> {
+    let prefix = prefix_bytes(instruction);
+    let mut spellings = vec![instruction.clone()];
+    if is_swappable(instruction.mnemonic) {
+        let mut swapped = instruction.clone();
+        swapped.operands.reverse();
+        spellings.push(swapped);
+    }
+    let mut found = Vec::new();
+    for encoding in INSTRUCTION_TABLE {
+        if encoding.mnemonic == instruction.mnemonic && !Shape::of(encoding).prefix {
+            for spelling in &spellings {
+                found.extend(candidates(encoding, spelling, &prefix, branch_target));
+            }
+        }
+    }
+
+    if instruction.width.is_none() {
+        let byte = found
+            .iter()
+            .any(|(_, decoded)| decoded.width == Some(Width::Byte));
+        let word = found
+            .iter()
+            .any(|(_, decoded)| decoded.width == Some(Width::Word));
+        if byte && word {
+            return Err(EncodeError::AmbiguousSize);
+        }
+    }
+    found
+        .into_iter()
+        .map(|(bytes, _)| bytes)
+        .min_by_key(Vec::len)
+        .ok_or(EncodeError::NoEncoding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::{EffectiveAddress, EffectiveAddressBase};
+    use crate::registers::Reg16;
+
+    fn register(register: Reg16) -> Operand {
+        Operand::Register(Register::Word(register))
+    }
+
+    #[test]
+    fn register_moves_use_the_d0_form() {
+        let instruction = Instruction::new(
+            Mnemonic::Mov,
+            vec![register(Reg16::Si), register(Reg16::Bx)],
+        );
+        assert_eq!(encode(&instruction, None), Ok(vec![0x89, 0xde]));
+    }
+
+    #[test]
+    fn bp_without_displacement_gets_a_zero_byte() {
+        let address = Operand::Memory(EffectiveAddress {
+            base: Some(EffectiveAddressBase::Bp),
+            displacement: None,
+        });
+        let instruction = Instruction::new(Mnemonic::Mov, vec![register(Reg16::Dx), address]);
+        assert_eq!(encode(&instruction, None), Ok(vec![0x8b, 0x56, 0x00]));
+    }
+
+    #[test]
+    fn memory_without_a_size_is_ambiguous() {
+        let address = Operand::Memory(EffectiveAddress {
+            base: Some(EffectiveAddressBase::Bx),
+            displacement: None,
+        });
+        let instruction = Instruction::new(Mnemonic::Inc, vec![address]);
+        assert_eq!(encode(&instruction, None), Err(EncodeError::AmbiguousSize));
+        let instruction = instruction.with_width(Width::Word);
+        assert_eq!(encode(&instruction, None), Ok(vec![0xff, 0x07]));
+    }
+}
@@ -0,0 +1,171 @@
+use std::fmt;
+use std::io;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeErrorKind {
+    /// The input ended in the middle of an instruction.
+    Truncated,
+    UnknownOpcode(u8),
+    /// The ModRM byte selects an encoding that is not defined for the opcode.
+    InvalidModRm {
+        opcode: u8,
+        modrm: u8,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError {
+    /// Offset of the first byte of the instruction, including any prefixes.
+    pub offset: usize,
+    pub kind: DecodeErrorKind,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            DecodeErrorKind::Truncated => {
+                write!(f, "truncated instruction at offset {:#x}", self.offset)
+            }
+            DecodeErrorKind::UnknownOpcode(opcode) => {
+                write!(
+                    f,
+                    "unknown opcode 0x{:02x} at offset {:#x}",
+                    opcode, self.offset
+                )
+            }
+            DecodeErrorKind::InvalidModRm { opcode, modrm } => write!(
+                f,
+                "invalid ModRM byte 0b{:08b} for opcode 0x{:02x} at offset {:#x}",
+                modrm, opcode, self.offset
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExecError {
+    Decode(DecodeError),
+    /// The instruction decoded fine but the simulator cannot execute it yet.
+    Unsupported {
+        address: usize,
+        instruction: String,
+    },
+    /// The program ran this many instructions without ending, so it was stopped.
+    StepLimit(usize),
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExecError::Decode(error) => write!(f, "{}", error),
+            ExecError::Unsupported {
+                address,
+                instruction,
+            } => write!(
+                f,
+                "cannot execute `{}` at offset {:#x}",
+                instruction, address
+            ),
+            ExecError::StepLimit(limit) => write!(
+                f,
+                "stopped after {} instructions without reaching the end of the program",
+                limit
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ExecError {}
+
+impl From<DecodeError> for ExecError {
+    fn from(error: DecodeError) -> ExecError {
+        ExecError::Decode(error)
+    }
+}
+
+/// Why a trace stopped: the program could not run on, or the trace could not be written.
+#[derive(Debug)]
+pub enum TraceError {
+    Exec(ExecError),
+    Io(io::Error),
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraceError::Exec(error) => write!(f, "{}", error),
+            TraceError::Io(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+impl From<ExecError> for TraceError {
+    fn from(error: ExecError) -> TraceError {
+        TraceError::Exec(error)
+    }
+}
+
+impl From<io::Error> for TraceError {
+    fn from(error: io::Error) -> TraceError {
+        TraceError::Io(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodeError {
+    /// No encoding of the mnemonic takes these operands.
+    NoEncoding,
+    /// A memory operand could be a byte or a word and nothing says which.
+    AmbiguousSize,
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EncodeError::NoEncoding => write!(f, "no encoding takes these operands"),
+            EncodeError::AmbiguousSize => write!(f, "operation size not specified"),
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssembleErrorKind {
+    Syntax(String),
+    UnknownMnemonic(String),
+    UndefinedLabel(String),
+    DuplicateLabel(String),
+    Encode(EncodeError),
+    /// Jump sizes kept changing the label addresses they depend on.
+    Unsettled,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembleError {
+    /// 1-based source line, or 0 when the error is not tied to one.
+    pub line: usize,
+    pub kind: AssembleErrorKind,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            AssembleErrorKind::Syntax(message) => write!(f, "{}", message),
+            AssembleErrorKind::UnknownMnemonic(name) => write!(f, "unknown mnemonic `{}`", name),
+            AssembleErrorKind::UndefinedLabel(name) => write!(f, "undefined label `{}`", name),
+            AssembleErrorKind::DuplicateLabel(name) => {
+                write!(f, "label `{}` is defined twice", name)
+            }
+            AssembleErrorKind::Encode(error) => write!(f, "{}", error),
+            AssembleErrorKind::Unsettled => write!(f, "label addresses do not settle"),
+        }
+    }
+}
+
+impl std::error::Error for AssembleError {}
@@ -0,0 +1,427 @@
+use std::fmt;
+
+use crate::registers::{Reg16, Register, SegReg};
+
+macro_rules! mnemonics {
+    ($($variant:ident => $name:literal,)*) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum Mnemonic {
+            $($variant,)*
+        }
+
+        impl Mnemonic {
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(Mnemonic::$variant => $name,)*
+                }
+            }
+
+            /// The mnemonic spelled `name`, in any case.
+            pub fn from_name(name: &str) -> Option<Mnemonic> {
+                match name.to_ascii_uppercase().as_str() {
+                    $($name => Some(Mnemonic::$variant),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+mnemonics! {
+    Mov => "MOV",
+    Push => "PUSH",
+    Pop => "POP",
+    Xchg => "XCHG",
+    In => "IN",
+    Out => "OUT",
+    Xlat => "XLAT",
+    Lea => "LEA",
+    Lds => "LDS",
+    Les => "LES",
+    Lahf => "LAHF",
+    Sahf => "SAHF",
+    Pushf => "PUSHF",
+    Popf => "POPF",
+    Add => "ADD",
+    Adc => "ADC",
+    Inc => "INC",
+    Aaa => "AAA",
+    Daa => "DAA",
+    Sub => "SUB",
+    Sbb => "SBB",
+    Dec => "DEC",
+    Neg => "NEG",
+    Cmp => "CMP",
+    Aas => "AAS",
+    Das => "DAS",
+    Mul => "MUL",
+    Imul => "IMUL",
+    Aam => "AAM",
+    Div => "DIV",
+    Idiv => "IDIV",
+    Aad => "AAD",
+    Cbw => "CBW",
+    Cwd => "CWD",
+    Not => "NOT",
+    Shl => "SHL",
+    Shr => "SHR",
+    Sar => "SAR",
+    Rol => "ROL",
+    Ror => "ROR",
+    Rcl => "RCL",
+    Rcr => "RCR",
+    And => "AND",
+    Test => "TEST",
+    Or => "OR",
+    Xor => "XOR",
+    Rep => "REP",
+    Movs => "MOVS",
+    Cmps => "CMPS",
+    Scas => "SCAS",
+    Lods => "LODS",
+    Stos => "STOS",
+    Call => "CALL",
+    Jmp => "JMP",
+    Ret => "RET",
+    Retf => "RETF",
+    Je => "JE",
+    Jl => "JL",
+    Jle => "JLE",
+    Jb => "JB",
+    Jbe => "JBE",
+    Jp => "JP",
+    Jo => "JO",
+    Js => "JS",
+    Jne => "JNE",
+    Jnl => "JNL",
+    Jg => "JG",
+    Jnb => "JNB",
+    Ja => "JA",
+    Jnp => "JNP",
+    Jno => "JNO",
+    Jns => "JNS",
+    Loop => "LOOP",
+    Loopz => "LOOPZ",
+    Loopnz => "LOOPNZ",
+    Jcxz => "JCXZ",
+    Int => "INT",
+    Int3 => "INT3",
+    Into => "INTO",
+    Iret => "IRET",
+    Clc => "CLC",
+    Cmc => "CMC",
+    Stc => "STC",
+    Cld => "CLD",
+    Std => "STD",
+    Cli => "CLI",
+    Sti => "STI",
+    Hlt => "HLT",
+    Wait => "WAIT",
+    Nop => "NOP",
+    Esc => "ESC",
+    Salc => "SALC",
+    // Prefixes; they only ever show up as attributes of the instruction they precede
+    Lock => "LOCK",
+    Segment => "SEGMENT",
+}
+
+impl Mnemonic {
+    pub fn is_shift(&self) -> bool {
+        matches!(
+            self,
+            Mnemonic::Shl
+                | Mnemonic::Shr
+                | Mnemonic::Sar
+                | Mnemonic::Rol
+                | Mnemonic::Ror
+                | Mnemonic::Rcl
+                | Mnemonic::Rcr
+        )
+    }
+
+    pub fn is_string(&self) -> bool {
+        matches!(
+            self,
+            Mnemonic::Movs | Mnemonic::Cmps | Mnemonic::Scas | Mnemonic::Lods | Mnemonic::Stos
+        )
+    }
+}
+
+impl fmt::Display for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Width {
+    Byte,
+    Word,
+}
+
+impl Width {
+    pub fn from_w_field(w_field: u8) -> Width {
+        if w_field == 0b1 {
+            Width::Word
+        } else {
+            Width::Byte
+        }
+    }
+}
+
+impl fmt::Display for Width {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Width::Byte => write!(f, "BYTE"),
+            Width::Word => write!(f, "WORD"),
+        }
+    }
+}
+
+/// The register part of an effective address, in R/M field encoding order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EffectiveAddressBase {
+    BxSi,
+    BxDi,
+    BpSi,
+    BpDi,
+    Si,
+    Di,
+    Bp,
+    Bx,
+}
+
+impl EffectiveAddressBase {
+    pub const ALL: [EffectiveAddressBase; 8] = [
+        EffectiveAddressBase::BxSi,
+        EffectiveAddressBase::BxDi,
+        EffectiveAddressBase::BpSi,
+        EffectiveAddressBase::BpDi,
+        EffectiveAddressBase::Si,
+        EffectiveAddressBase::Di,
+        EffectiveAddressBase::Bp,
+        EffectiveAddressBase::Bx,
+    ];
+
+    /// The base an R/M field selects when MOD is not 11.
+    pub fn from_rm_field(rm_field: u8) -> EffectiveAddressBase {
+        EffectiveAddressBase::ALL[(rm_field & 0b111) as usize]
+    }
+
+    /// The R/M field that selects this base.
+    pub fn rm_field(&self) -> u8 {
+        *self as u8
+    }
+
+    /// The registers that are summed to form the address.
+    pub fn registers(&self) -> &'static [Reg16] {
+        match self {
+            EffectiveAddressBase::BxSi => &[Reg16::Bx, Reg16::Si],
+            EffectiveAddressBase::BxDi => &[Reg16::Bx, Reg16::Di],
+            EffectiveAddressBase::BpSi => &[Reg16::Bp, Reg16::Si],
+            EffectiveAddressBase::BpDi => &[Reg16::Bp, Reg16::Di],
+            EffectiveAddressBase::Si => &[Reg16::Si],
+            EffectiveAddressBase::Di => &[Reg16::Di],
+            EffectiveAddressBase::Bp => &[Reg16::Bp],
+            EffectiveAddressBase::Bx => &[Reg16::Bx],
+        }
+    }
+
+    /// Addresses based on BP default to the stack segment, everything else to DS.
+    pub fn default_segment(&self) -> SegReg {
+        if self.registers().contains(&Reg16::Bp) {
+            SegReg::Ss
+        } else {
+            SegReg::Ds
+        }
+    }
+}
+
+impl fmt::Display for EffectiveAddressBase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut separator = "";
+        for register in self.registers() {
+            write!(f, "{}{}", separator, register)?;
+            separator = " + ";
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EffectiveAddress {
+    /// Register expression from the R/M field, e.g. `BX + SI`. `None` for a direct address.
+    pub base: Option<EffectiveAddressBase>,
+    pub displacement: Option<i32>,
+}
+
+impl fmt::Display for EffectiveAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.base, self.displacement) {
+            (Some(base), None) => write!(f, "[{}]", base),
+            (Some(base), Some(displacement)) if displacement < 0 => {
+                write!(f, "[{} - {}]", base, -displacement)
+            }
+            (Some(base), Some(displacement)) => write!(f, "[{} + {}]", base, displacement),
+            (None, displacement) => write!(f, "[{}]", displacement.unwrap_or(0)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Register(Register),
+    Memory(EffectiveAddress),
+    Immediate(i32),
+    /// Signed jump displacement, relative to the end of the instruction.
+    Relative(i16),
+    Far {
+        segment: u16,
+        offset: u16,
+    },
+}
+
+/// Which repeat prefix an instruction carries. The Z bit tells them apart: 0xF3 repeats
+/// while CX is nonzero (and, for CMPS/SCAS, while ZF is set), 0xF2 while ZF is clear.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repeat {
+    Rep,
+    Repne,
+}
+
+impl fmt::Display for Repeat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Repeat::Rep => write!(f, "REP"),
+            Repeat::Repne => write!(f, "REPNE"),
+        }
+    }
+}
+
+/// LOCK, REP/REPNE and segment override prefixes, folded into the instruction they precede.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Prefixes {
+    pub lock: bool,
+    pub rep: Option<Repeat>,
+    pub segment: Option<SegReg>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub address: usize,
+    pub length: usize,
+    pub mnemonic: Mnemonic,
+    pub operands: Vec<Operand>,
+    pub width: Option<Width>,
+    pub prefixes: Prefixes,
+    pub far: bool,
+    /// Decoded from the A0-A3 MOV forms, which carry a direct address in place of a ModRM
+    /// byte and need no effective address calculation.
+    pub accumulator_direct: bool,
+    /// How wide the immediate data is in the encoding, which can be narrower than the
+    /// operation when a byte is sign-extended. `None` when nothing was encoded as data.
+    pub data_width: Option<Width>,
+}
+
+impl Instruction {
+    pub fn new(mnemonic: Mnemonic, operands: Vec<Operand>) -> Instruction {
+        Instruction {
+            address: 0,
+            length: 0,
+            mnemonic,
+            operands,
+            width: None,
+            prefixes: Prefixes::default(),
+            far: false,
+            accumulator_direct: false,
+            data_width: None,
+        }
+    }
+
+    pub fn with_width(mut self, width: Width) -> Instruction {
+        self.width = Some(width);
+        self
+    }
+
+    /// Absolute target of a relative jump or call: the displacement is taken from the end
+    /// of the instruction and wraps within the 64K code segment.
+    pub fn branch_target(&self) -> Option<u16> {
+        self.operands.iter().find_map(|operand| match operand {
+            Operand::Relative(displacement) => {
+                Some(((self.address + self.length) as u16).wrapping_add(*displacement as u16))
+            }
+            _ => None,
+        })
+    }
+
+    /// The prefixes as NASM spells them ahead of the mnemonic, e.g. `LOCK ` or `REP ES `.
+    /// A segment override goes here only when no memory operand shows it, as on string
+    /// instructions.
+    pub fn prefix_text(&self) -> String {
+        let mut text = String::new();
+        if self.prefixes.lock {
+            text.push_str("LOCK ");
+        }
+        if let Some(repeat) = self.prefixes.rep {
+            text.push_str(&format!("{} ", repeat));
+        }
+        let memory = self
+            .operands
+            .iter()
+            .any(|operand| matches!(operand, Operand::Memory(_)));
+        if let (Some(segment), false) = (self.prefixes.segment, memory) {
+            text.push_str(&format!("{} ", segment));
+        }
+        text
+    }
+
+    /// A memory operand needs an explicit size when no register operand implies one.
+    /// Shift counts in CL say nothing about the width of the shifted operand.
+    fn needs_size(&self) -> bool {
+        !self.far
+            && (self.mnemonic.is_shift()
+                || !self
+                    .operands
+                    .iter()
+                    .any(|operand| matches!(operand, Operand::Register(_))))
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.prefix_text(), self.mnemonic)?;
+        if self.mnemonic.is_string() {
+            match self.width {
+                Some(Width::Byte) => write!(f, "B")?,
+                Some(Width::Word) => write!(f, "W")?,
+                None => {}
+            }
+        }
+
+        let mut separator = " ";
+        for operand in &self.operands {
+            write!(f, "{}", separator)?;
+            separator = ", ";
+            match operand {
+                Operand::Register(register) => write!(f, "{}", register)?,
+                Operand::Memory(address) => {
+                    if self.far {
+                        write!(f, "FAR ")?;
+                    } else if let (true, Some(width)) = (self.needs_size(), self.width) {
+                        write!(f, "{} ", width)?;
+                    }
+                    if let Some(segment) = self.prefixes.segment {
+                        write!(f, "{}:", segment)?;
+                    }
+                    write!(f, "{}", address)?;
+                }
+                Operand::Immediate(value) => write!(f, "{}", value)?,
+                Operand::Relative(displacement) => {
+                    write!(f, "${:+}", *displacement as i32 + self.length as i32)?
+                }
+                Operand::Far { segment, offset } => write!(f, "{}:{}", segment, offset)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,289 @@
+//! The 8086 encodings, transcribed from table 4-12 of the Intel 8086 manual by way of
+//! `sim86_instruction_table.inl`. Each entry is the sequence of bit fields that makes up the
+//! instruction, most significant bit first, so adding or fixing an instruction is a table edit.
+
+use crate::instruction::Mnemonic;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    /// Bits that must match exactly for the encoding to apply.
+    Literal {
+        count: u8,
+        value: u8,
+    },
+    D,
+    S,
+    W,
+    /// Shift count source: 1 when clear, CL when set.
+    V,
+    /// Repeat while zero (REP/REPE) when set, while not zero (REPNE) when clear.
+    Z,
+    Mod,
+    Reg,
+    Rm,
+    Sr,
+    /// Three bits of the external opcode of ESC, at the given shift.
+    Esc(u8),
+    /// Bits the 8086 does not look at: any value decodes, and zeros are emitted.
+    Any(u8),
+
+    // Fields the encoding fixes without spending any bits on them
+    ImpW(u8),
+    ImpReg(u8),
+    ImpMod(u8),
+    ImpRm(u8),
+    ImpD(u8),
+    ImpS(u8),
+
+    /// An 8-bit displacement, sign-extended.
+    Disp,
+    /// A 16-bit displacement or address.
+    Addr,
+    /// An 8-bit immediate, or 16-bit with `DataIfW`.
+    Data,
+    /// The immediate is a word when W is set and S is clear.
+    DataIfW,
+    /// R/M names a word register regardless of W (`in al, dx`).
+    RmRegAlwaysW,
+    /// The displacement is a jump relative to the end of the instruction.
+    RelJmpDisp,
+    Far,
+    /// R/M must name memory; MOD = 11 is not a valid encoding.
+    MemoryOnly,
+}
+
+use Field::*;
+
+/// `b("100010")` is six literal bits, spelled the way the manual prints them.
+const fn b(bits: &str) -> Field {
+    let bits = bits.as_bytes();
+    let mut value = 0;
+    let mut i = 0;
+    while i < bits.len() {
+        value = value << 1 | (bits[i] - b'0');
+        i += 1;
+    }
+    Literal {
+        count: bits.len() as u8,
+        value,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Encoding {
+    pub mnemonic: Mnemonic,
+    pub fields: &'static [Field],
+}
+
+const fn inst(mnemonic: Mnemonic, fields: &'static [Field]) -> Encoding {
+    Encoding { mnemonic, fields }
+}
+
+/// Every encoding, tried in order; the first one whose literal bits match wins.
+#[rustfmt::skip]
+pub static INSTRUCTION_TABLE: &[Encoding] = &[
+    inst(Mnemonic::Mov, &[b("100010"), D, W, Mod, Reg, Rm]),
+    inst(Mnemonic::Mov, &[b("1100011"), W, Mod, b("000"), Rm, Data, DataIfW, ImpD(0)]),
+    inst(Mnemonic::Mov, &[b("1011"), W, Reg, Data, DataIfW, ImpD(1)]),
+    inst(Mnemonic::Mov, &[b("1010000"), W, Addr, ImpReg(0), ImpMod(0), ImpRm(0b110), ImpD(1)]),
+    inst(Mnemonic::Mov, &[b("1010001"), W, Addr, ImpReg(0), ImpMod(0), ImpRm(0b110), ImpD(0)]),
+    // Collapses the two segment register moves of the manual with an explicit D bit
+    inst(Mnemonic::Mov, &[b("100011"), D, b("0"), Mod, b("0"), Sr, Rm, ImpW(1)]),
+
+    inst(Mnemonic::Push, &[b("11111111"), Mod, b("110"), Rm, ImpW(1)]),
+    inst(Mnemonic::Push, &[b("01010"), Reg, ImpW(1)]),
+    inst(Mnemonic::Push, &[b("000"), Sr, b("110"), ImpW(1)]),
+
+    inst(Mnemonic::Pop, &[b("10001111"), Mod, b("000"), Rm, ImpW(1)]),
+    inst(Mnemonic::Pop, &[b("01011"), Reg, ImpW(1)]),
+    inst(Mnemonic::Pop, &[b("000"), Sr, b("111"), ImpW(1)]),
+
+    // 0x90 is xchg ax, ax, but everyone calls it nop
+    inst(Mnemonic::Nop, &[b("10010000")]),
+    inst(Mnemonic::Xchg, &[b("1000011"), W, Mod, Reg, Rm, ImpD(1)]),
+    inst(Mnemonic::Xchg, &[b("10010"), Reg, ImpMod(0b11), ImpW(1), ImpRm(0)]),
+
+    inst(Mnemonic::In, &[b("1110010"), W, Data, ImpReg(0), ImpD(1)]),
+    inst(Mnemonic::In, &[b("1110110"), W, ImpReg(0), ImpD(1), ImpMod(0b11), ImpRm(2), RmRegAlwaysW]),
+    inst(Mnemonic::Out, &[b("1110011"), W, Data, ImpReg(0), ImpD(0)]),
+    inst(Mnemonic::Out, &[b("1110111"), W, ImpReg(0), ImpD(0), ImpMod(0b11), ImpRm(2), RmRegAlwaysW]),
+
+    inst(Mnemonic::Xlat, &[b("11010111")]),
+    inst(Mnemonic::Lea, &[b("10001101"), Mod, Reg, Rm, ImpD(1), ImpW(1), MemoryOnly]),
+    inst(Mnemonic::Lds, &[b("11000101"), Mod, Reg, Rm, ImpD(1), ImpW(1), MemoryOnly]),
+    inst(Mnemonic::Les, &[b("11000100"), Mod, Reg, Rm, ImpD(1), ImpW(1), MemoryOnly]),
+    inst(Mnemonic::Lahf, &[b("10011111")]),
+    inst(Mnemonic::Sahf, &[b("10011110")]),
+    inst(Mnemonic::Pushf, &[b("10011100")]),
+    inst(Mnemonic::Popf, &[b("10011101")]),
+
+    inst(Mnemonic::Add, &[b("000000"), D, W, Mod, Reg, Rm]),
+    inst(Mnemonic::Add, &[b("100000"), S, W, Mod, b("000"), Rm, Data, DataIfW]),
+    inst(Mnemonic::Add, &[b("0000010"), W, Data, DataIfW, ImpReg(0), ImpD(1)]),
+
+    inst(Mnemonic::Adc, &[b("000100"), D, W, Mod, Reg, Rm]),
+    inst(Mnemonic::Adc, &[b("100000"), S, W, Mod, b("010"), Rm, Data, DataIfW]),
+    inst(Mnemonic::Adc, &[b("0001010"), W, Data, DataIfW, ImpReg(0), ImpD(1)]),
+
+    inst(Mnemonic::Inc, &[b("1111111"), W, Mod, b("000"), Rm]),
+    inst(Mnemonic::Inc, &[b("01000"), Reg, ImpW(1)]),
+
+    inst(Mnemonic::Aaa, &[b("00110111")]),
+    inst(Mnemonic::Daa, &[b("00100111")]),
+
+    inst(Mnemonic::Sub, &[b("001010"), D, W, Mod, Reg, Rm]),
+    inst(Mnemonic::Sub, &[b("100000"), S, W, Mod, b("101"), Rm, Data, DataIfW]),
+    inst(Mnemonic::Sub, &[b("0010110"), W, Data, DataIfW, ImpReg(0), ImpD(1)]),
+
+    inst(Mnemonic::Sbb, &[b("000110"), D, W, Mod, Reg, Rm]),
+    inst(Mnemonic::Sbb, &[b("100000"), S, W, Mod, b("011"), Rm, Data, DataIfW]),
+    inst(Mnemonic::Sbb, &[b("0001110"), W, Data, DataIfW, ImpReg(0), ImpD(1)]),
+
+    inst(Mnemonic::Dec, &[b("1111111"), W, Mod, b("001"), Rm]),
+    inst(Mnemonic::Dec, &[b("01001"), Reg, ImpW(1)]),
+
+    inst(Mnemonic::Neg, &[b("1111011"), W, Mod, b("011"), Rm]),
+
+    inst(Mnemonic::Cmp, &[b("001110"), D, W, Mod, Reg, Rm]),
+    inst(Mnemonic::Cmp, &[b("100000"), S, W, Mod, b("111"), Rm, Data, DataIfW]),
+    inst(Mnemonic::Cmp, &[b("0011110"), W, Data, DataIfW, ImpReg(0), ImpD(1)]),
+
+    inst(Mnemonic::Aas, &[b("00111111")]),
+    inst(Mnemonic::Das, &[b("00101111")]),
+    inst(Mnemonic::Mul, &[b("1111011"), W, Mod, b("100"), Rm, ImpS(0)]),
+    inst(Mnemonic::Imul, &[b("1111011"), W, Mod, b("101"), Rm, ImpS(1)]),
+    inst(Mnemonic::Aam, &[b("11010100"), b("00001010")]),
+    inst(Mnemonic::Div, &[b("1111011"), W, Mod, b("110"), Rm, ImpS(0)]),
+    inst(Mnemonic::Idiv, &[b("1111011"), W, Mod, b("111"), Rm, ImpS(1)]),
+    inst(Mnemonic::Aad, &[b("11010101"), b("00001010")]),
+    inst(Mnemonic::Cbw, &[b("10011000")]),
+    inst(Mnemonic::Cwd, &[b("10011001")]),
+
+    inst(Mnemonic::Not, &[b("1111011"), W, Mod, b("010"), Rm]),
+    inst(Mnemonic::Shl, &[b("110100"), V, W, Mod, b("100"), Rm]),
+    inst(Mnemonic::Shr, &[b("110100"), V, W, Mod, b("101"), Rm]),
+    inst(Mnemonic::Sar, &[b("110100"), V, W, Mod, b("111"), Rm]),
+    inst(Mnemonic::Rol, &[b("110100"), V, W, Mod, b("000"), Rm]),
+    inst(Mnemonic::Ror, &[b("110100"), V, W, Mod, b("001"), Rm]),
+    inst(Mnemonic::Rcl, &[b("110100"), V, W, Mod, b("010"), Rm]),
+    inst(Mnemonic::Rcr, &[b("110100"), V, W, Mod, b("011"), Rm]),
+
+    // The manual only gives AND, OR and XOR the 1000000w form, but the 8086 honours S for
+    // them like it does for ADD, so 0x82/0x83 decode too. The reference decoder follows the
+    // manual, so tests/differential.rs lists these as an intentional divergence.
+    inst(Mnemonic::And, &[b("001000"), D, W, Mod, Reg, Rm]),
+    inst(Mnemonic::And, &[b("100000"), S, W, Mod, b("100"), Rm, Data, DataIfW]),
+    inst(Mnemonic::And, &[b("0010010"), W, Data, DataIfW, ImpReg(0), ImpD(1)]),
+
+    // No D bit here, whatever the manual suggests; it would collide with XCHG
+    inst(Mnemonic::Test, &[b("1000010"), W, Mod, Reg, Rm]),
+    inst(Mnemonic::Test, &[b("1111011"), W, Mod, b("000"), Rm, Data, DataIfW]),
+    inst(Mnemonic::Test, &[b("1010100"), W, Data, DataIfW, ImpReg(0), ImpD(1)]),
+
+    inst(Mnemonic::Or, &[b("000010"), D, W, Mod, Reg, Rm]),
+    inst(Mnemonic::Or, &[b("100000"), S, W, Mod, b("001"), Rm, Data, DataIfW]),
+    inst(Mnemonic::Or, &[b("0000110"), W, Data, DataIfW, ImpReg(0), ImpD(1)]),
+
+    inst(Mnemonic::Xor, &[b("001100"), D, W, Mod, Reg, Rm]),
+    inst(Mnemonic::Xor, &[b("100000"), S, W, Mod, b("110"), Rm, Data, DataIfW]),
+    inst(Mnemonic::Xor, &[b("0011010"), W, Data, DataIfW, ImpReg(0), ImpD(1)]),
+
+    inst(Mnemonic::Rep, &[b("1111001"), Z]),
+    inst(Mnemonic::Movs, &[b("1010010"), W]),
+    inst(Mnemonic::Cmps, &[b("1010011"), W]),
+    inst(Mnemonic::Scas, &[b("1010111"), W]),
+    inst(Mnemonic::Lods, &[b("1010110"), W]),
+    inst(Mnemonic::Stos, &[b("1010101"), W]),
+
+    inst(Mnemonic::Call, &[b("11101000"), Addr, RelJmpDisp]),
+    inst(Mnemonic::Call, &[b("11111111"), Mod, b("010"), Rm, ImpW(1)]),
+    inst(Mnemonic::Call, &[b("10011010"), Addr, Data, DataIfW, ImpW(1)]),
+    inst(Mnemonic::Call, &[b("11111111"), Mod, b("011"), Rm, ImpW(1), Far]),
+
+    inst(Mnemonic::Jmp, &[b("11101001"), Addr, RelJmpDisp]),
+    inst(Mnemonic::Jmp, &[b("11101011"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Jmp, &[b("11111111"), Mod, b("100"), Rm, ImpW(1)]),
+    inst(Mnemonic::Jmp, &[b("11101010"), Addr, Data, DataIfW, ImpW(1)]),
+    inst(Mnemonic::Jmp, &[b("11111111"), Mod, b("101"), Rm, ImpW(1), Far]),
+
+    // The manual does not tell RET and RETF apart, but NASM needs them to reassemble
+    inst(Mnemonic::Ret, &[b("11000011")]),
+    inst(Mnemonic::Ret, &[b("11000010"), Data, DataIfW, ImpW(1)]),
+    inst(Mnemonic::Retf, &[b("11001011")]),
+    inst(Mnemonic::Retf, &[b("11001010"), Data, DataIfW, ImpW(1)]),
+    // The 8086 ignores bit 1 of these opcodes, so 0xC0/0xC1/0xC8/0xC9 are RET and RETF too
+    inst(Mnemonic::Ret, &[b("11000001")]),
+    inst(Mnemonic::Ret, &[b("11000000"), Data, DataIfW, ImpW(1)]),
+    inst(Mnemonic::Retf, &[b("11001001")]),
+    inst(Mnemonic::Retf, &[b("11001000"), Data, DataIfW, ImpW(1)]),
+
+    inst(Mnemonic::Je, &[b("01110100"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Jl, &[b("01111100"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Jle, &[b("01111110"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Jb, &[b("01110010"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Jbe, &[b("01110110"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Jp, &[b("01111010"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Jo, &[b("01110000"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Js, &[b("01111000"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Jne, &[b("01110101"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Jnl, &[b("01111101"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Jg, &[b("01111111"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Jnb, &[b("01110011"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Ja, &[b("01110111"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Jnp, &[b("01111011"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Jno, &[b("01110001"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Jns, &[b("01111001"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Loop, &[b("11100010"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Loopz, &[b("11100001"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Loopnz, &[b("11100000"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Jcxz, &[b("11100011"), Disp, RelJmpDisp]),
+    // Undocumented: the 8086 ignores bit 4 here, so 0x60-0x6F repeat the conditional jumps
+    inst(Mnemonic::Jo, &[b("01100000"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Jno, &[b("01100001"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Jb, &[b("01100010"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Jnb, &[b("01100011"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Je, &[b("01100100"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Jne, &[b("01100101"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Jbe, &[b("01100110"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Ja, &[b("01100111"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Js, &[b("01101000"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Jns, &[b("01101001"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Jp, &[b("01101010"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Jnp, &[b("01101011"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Jl, &[b("01101100"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Jnl, &[b("01101101"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Jle, &[b("01101110"), Disp, RelJmpDisp]),
+    inst(Mnemonic::Jg, &[b("01101111"), Disp, RelJmpDisp]),
+
+    inst(Mnemonic::Int, &[b("11001101"), Data]),
+    inst(Mnemonic::Int3, &[b("11001100")]),
+
+    inst(Mnemonic::Into, &[b("11001110")]),
+    inst(Mnemonic::Iret, &[b("11001111")]),
+
+    inst(Mnemonic::Clc, &[b("11111000")]),
+    inst(Mnemonic::Cmc, &[b("11110101")]),
+    inst(Mnemonic::Stc, &[b("11111001")]),
+    inst(Mnemonic::Cld, &[b("11111100")]),
+    inst(Mnemonic::Std, &[b("11111101")]),
+    inst(Mnemonic::Cli, &[b("11111010")]),
+    inst(Mnemonic::Sti, &[b("11111011")]),
+    inst(Mnemonic::Hlt, &[b("11110100")]),
+    inst(Mnemonic::Wait, &[b("10011011")]),
+    // Undocumented: AL = CF ? 0xFF : 0
+    inst(Mnemonic::Salc, &[b("11010110")]),
+    inst(Mnemonic::Esc, &[b("11011"), Esc(3), Mod, Esc(0), Rm]),
+    inst(Mnemonic::Lock, &[b("11110000")]),
+    // Undocumented alias of LOCK
+    inst(Mnemonic::Lock, &[b("11110001")]),
+    inst(Mnemonic::Segment, &[b("001"), Sr, b("110")]),
+
+    // Undocumented: the 8086 only checks REG where it selects the operation, so the unused
+    // values of these opcodes repeat the documented instruction
+    inst(Mnemonic::Mov, &[b("1100011"), W, Mod, Any(3), Rm, Data, DataIfW, ImpD(0)]),
+    inst(Mnemonic::Pop, &[b("10001111"), Mod, Any(3), Rm, ImpW(1)]),
+    inst(Mnemonic::Push, &[b("11111111"), Mod, b("111"), Rm, ImpW(1)]),
+    inst(Mnemonic::Test, &[b("1111011"), W, Mod, b("001"), Rm, Data, DataIfW]),
+    // Undocumented: only two bits name the segment register, the third is ignored
+    inst(Mnemonic::Mov, &[b("100011"), D, b("0"), Mod, b("1"), Sr, Rm, ImpW(1)]),
+];
@@ -0,0 +1,222 @@
+//! JSON Lines rendering of a disassembly: one self-contained object per instruction, for
+//! scripts that would otherwise have to parse the assembly text.
+
+use std::fmt::Write;
+
+use crate::error::DecodeError;
+use crate::instruction::{Instruction, Operand, Width};
+use crate::labels::Labels;
+use crate::registers::{Register, SegReg};
+
+/// Quotes and escapes `text` as a JSON string.
+fn string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn optional<T>(value: Option<T>, render: impl FnOnce(T) -> String) -> String {
+    value.map_or_else(|| "null".to_string(), render)
+}
+
+fn size(width: Option<Width>) -> String {
+    optional(width, |width| match width {
+        Width::Byte => "8".to_string(),
+        Width::Word => "16".to_string(),
+    })
+}
+
+fn segment_name(segment: SegReg) -> String {
+    string(&segment.name().to_lowercase())
+}
+
+fn operand(instruction: &Instruction, operand: &Operand) -> String {
+    match operand {
+        Operand::Register(register) => {
+            let bits = match register {
+                Register::Byte(_) => 8,
+                Register::Word(_) | Register::Segment(_) => 16,
+            };
+            format!(
+                r#"{{"kind":"register","register":{},"size":{}}}"#,
+                string(&register.to_string().to_lowercase()),
+                bits
+            )
+        }
+        Operand::Memory(address) => {
+            // The segment the access actually goes to, whether overridden or by default
+            let segment = instruction.prefixes.segment.unwrap_or_else(|| {
+                address
+                    .base
+                    .map_or(SegReg::Ds, |base| base.default_segment())
+            });
+            let base: Vec<String> = address
+                .base
+                .map(|base| {
+                    base.registers()
+                        .iter()
+                        .map(|register| string(&register.name().to_lowercase()))
+                        .collect()
+                })
+                .unwrap_or_default();
+            format!(
+                r#"{{"kind":"memory","size":{},"segment":{},"base":[{}],"displacement":{}}}"#,
+                size(instruction.width),
+                segment_name(segment),
+                base.join(","),
+                optional(address.displacement, |value| value.to_string())
+            )
+        }
+        Operand::Immediate(value) => format!(
+            r#"{{"kind":"immediate","size":{},"value":{}}}"#,
+            size(instruction.data_width),
+            value
+        ),
+        Operand::Relative(displacement) => format!(
+            r#"{{"kind":"relative","displacement":{},"target":{}}}"#,
+            displacement,
+            optional(instruction.branch_target(), |target| target.to_string())
+        ),
+        Operand::Far { segment, offset } => format!(
+            r#"{{"kind":"far","segment":{},"offset":{}}}"#,
+            segment, offset
+        ),
+    }
+}
+
+/// One decoded instruction. `bytes` are its raw bytes, prefixes included; `labels` supply
+/// the `label` field and the label names in `text`.
+pub fn instruction(instruction: &Instruction, bytes: &[u8], labels: &Labels) -> String {
+    let operands: Vec<String> = instruction
+        .operands
+        .iter()
+        .map(|entry| operand(instruction, entry))
+        .collect();
+    let prefixes = &instruction.prefixes;
+    format!(
+        concat!(
+            r#"{{"address":{},"bytes":{},"length":{},"label":{},"mnemonic":{},"#,
+            r#""prefixes":{{"lock":{},"rep":{},"segment":{}}},"far":{},"size":{},"#,
+            r#""operands":[{}],"text":{}}}"#
+        ),
+        instruction.address,
+        string(&hex(bytes)),
+        instruction.length,
+        optional(labels.at(instruction.address), string),
+        string(&instruction.mnemonic.name().to_lowercase()),
+        prefixes.lock,
+        optional(prefixes.rep, |rep| string(&rep.to_string().to_lowercase())),
+        optional(prefixes.segment, segment_name),
+        instruction.far,
+        size(instruction.width),
+        operands.join(","),
+        string(&labels.render(instruction))
+    )
+}
+
+/// A byte that did not decode and was skipped over.
+pub fn skipped(error: &DecodeError, byte: u8) -> String {
+    format!(
+        r#"{{"address":{},"bytes":{},"length":1,"error":{}}}"#,
+        error.offset,
+        string(&hex(&[byte])),
+        string(&error.to_string())
+    )
+}
+
+/// An error that stopped the disassembly.
+pub fn error(error: &DecodeError) -> String {
+    format!(
+        r#"{{"address":{},"error":{}}}"#,
+        error.offset,
+        string(&error.to_string())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DecodeErrorKind, Decoder};
+
+    #[test]
+    fn instructions_are_single_line_objects() {
+        // mov cx, es:[bx + 2]; jne $-4
+        let bytes = [0x26, 0x8b, 0x4f, 0x02, 0x75, 0xfa];
+        let mut decoder = Decoder::new(&bytes[..]);
+        let mov = decoder.next().unwrap().unwrap();
+        assert_eq!(
+            instruction(&mov, decoder.bytes(), &Labels::default()),
+            concat!(
+                r#"{"address":0,"bytes":"268b4f02","length":4,"label":null,"mnemonic":"mov","#,
+                r#""prefixes":{"lock":false,"rep":null,"segment":"es"},"far":false,"size":16,"#,
+                r#""operands":[{"kind":"register","register":"cx","size":16},"#,
+                r#"{"kind":"memory","size":16,"segment":"es","base":["bx"],"displacement":2}],"#,
+                r#""text":"MOV CX, ES:[BX + 2]"}"#
+            )
+        );
+
+        let jne = decoder.next().unwrap().unwrap();
+        let labels = Labels::collect(&[mov, jne.clone()]);
+        let line = instruction(&jne, decoder.bytes(), &labels);
+        assert!(line.contains(
+            r#""operands":[{"kind":"relative","displacement":-6,"target":0}],"text":"JNE label_0""#
+        ));
+    }
+
+    #[test]
+    fn immediates_report_their_encoded_size() {
+        // shl word [bx], 1; in al, 0x60; add word [bx], -1; ret 4; mov word [bx], 5;
+        // add byte [bx], -1 (0x82); add word es:[bx], -1
+        let bytes = [
+            0xd1, 0x27, 0xe4, 0x60, 0x83, 0x07, 0xff, 0xc2, 0x04, 0x00, 0xc7, 0x07, 0x05, 0x00,
+            0x82, 0x07, 0xff, 0x26, 0x83, 0x07, 0xff,
+        ];
+        let mut decoder = Decoder::new(&bytes[..]);
+        let mut immediates = Vec::new();
+        while let Some(decoded) = decoder.next() {
+            let line = instruction(&decoded.unwrap(), decoder.bytes(), &Labels::default());
+            let start = line.find(r#"{"kind":"immediate""#).unwrap();
+            let end = start + line[start..].find('}').unwrap() + 1;
+            immediates.push(line[start..end].to_string());
+        }
+        assert_eq!(
+            immediates,
+            [
+                r#"{"kind":"immediate","size":null,"value":1}"#,
+                r#"{"kind":"immediate","size":8,"value":96}"#,
+                r#"{"kind":"immediate","size":8,"value":-1}"#,
+                r#"{"kind":"immediate","size":16,"value":4}"#,
+                r#"{"kind":"immediate","size":16,"value":5}"#,
+                r#"{"kind":"immediate","size":8,"value":-1}"#,
+                r#"{"kind":"immediate","size":8,"value":-1}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn skipped_bytes_carry_the_error() {
+        let error = DecodeError {
+            offset: 3,
+            kind: DecodeErrorKind::Truncated,
+        };
+        assert_eq!(
+            skipped(&error, 0x8b),
+            r#"{"address":3,"bytes":"8b","length":1,"error":"truncated instruction at offset 0x3"}"#
+        );
+        assert_eq!(string("a\"b\\\u{1}"), r#""a\"b\\\u0001""#);
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::BTreeMap;
+
+use crate::instruction::{Instruction, Operand};
+
+/// Names for the branch targets of a disassembly, for printing `label_N:` lines instead of
+/// `$+N` operands. Only targets that start a decoded instruction get a name; anything else
+/// (mid-instruction or past the end) keeps its relative form, which assembles to the same
+/// displacement.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Labels {
+    names: BTreeMap<usize, String>,
+}
+
+impl Labels {
+    /// First pass: collects every jump, loop and call target in `instructions`, numbering
+    /// the labels in address order.
+    pub fn collect(instructions: &[Instruction]) -> Labels {
+        let starts: Vec<usize> = instructions
+            .iter()
+            .map(|instruction| instruction.address)
+            .collect();
+        let mut targets: Vec<usize> = instructions
+            .iter()
+            .filter_map(|instruction| instruction.branch_target())
+            .map(|target| target as usize)
+            .filter(|target| starts.binary_search(target).is_ok())
+            .collect();
+        targets.sort_unstable();
+        targets.dedup();
+
+        let names = targets
+            .into_iter()
+            .enumerate()
+            .map(|(index, address)| (address, format!("label_{}", index)))
+            .collect();
+        Labels { names }
+    }
+
+    /// The label at `address`, if something branches there.
+    pub fn at(&self, address: usize) -> Option<&str> {
+        self.names.get(&address).map(String::as_str)
+    }
+
+    /// Second pass: renders `instruction` with its branch operand replaced by the label name.
+    pub fn render(&self, instruction: &Instruction) -> String {
+        let label = instruction
+            .branch_target()
+            .and_then(|target| self.at(target as usize));
+        match (label, instruction.operands.as_slice()) {
+            (Some(label), [Operand::Relative(_)]) => {
+                format!(
+                    "{}{} {}",
+                    instruction.prefix_text(),
+                    instruction.mnemonic,
+                    label
+                )
+            }
+            _ => instruction.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, decode};
+
+    /// The listing `--labels` prints, label lines included.
+    fn labelled_text(instructions: &[Instruction], labels: &Labels) -> String {
+        let mut text = String::from("bits 16\n");
+        for instruction in instructions {
+            if let Some(label) = labels.at(instruction.address) {
+                text.push_str(&format!("{}:\n", label));
+            }
+            text.push_str(&labels.render(instruction));
+            text.push('\n');
+        }
+        text
+    }
+
+    #[test]
+    fn loops_branch_to_labels() {
+        // mov cx, 3; add bx, 10; loop $-3; jmp $+0 (to itself); jmp $+2 (past the end)
+        let instructions = decode(&[
+            0xb9, 0x03, 0x00, 0x83, 0xc3, 0x0a, 0xe2, 0xfb, 0xeb, 0xfe, 0xeb, 0x00,
+        ])
+        .unwrap();
+        let labels = Labels::collect(&instructions);
+
+        assert_eq!(labels.at(3), Some("label_0"));
+        assert_eq!(labels.at(8), Some("label_1"));
+        assert_eq!(labels.at(12), None);
+        assert_eq!(labels.render(&instructions[2]), "LOOP label_0");
+        assert_eq!(labels.render(&instructions[3]), "JMP label_1");
+        assert_eq!(labels.render(&instructions[4]), "JMP $+2");
+    }
+
+    #[test]
+    fn labelled_output_reassembles_to_the_same_bytes() {
+        // The loop above, plus jumps into the middle of an instruction and backwards past
+        // the start, which keep their relative form
+        let program = [
+            0xb9, 0x03, 0x00, 0x83, 0xc3, 0x0a, 0xe2, 0xfb, 0xeb, 0xfe, 0x75, 0xf6, 0xeb, 0xf0,
+            0xeb, 0x00,
+        ];
+        let instructions = decode(&program).unwrap();
+        let labels = Labels::collect(&instructions);
+        let text = labelled_text(&instructions, &labels);
+        assert!(text.contains("JNE $-8"), "{}", text);
+        assert_eq!(assemble(&text).unwrap(), program);
+    }
+}
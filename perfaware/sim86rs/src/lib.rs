@@ -0,0 +1,151 @@
+pub mod assembler;
+pub mod clocks;
+pub mod decoding_table;
+pub mod encoding_table;
+pub mod error;
+pub mod instruction;
+pub mod instruction_table;
+pub mod json;
+pub mod labels;
+pub mod listing;
+pub mod memory;
+pub mod registers;
+pub mod simulator;
+pub mod source;
+pub mod text;
+pub mod trace;
+
+pub use assembler::assemble;
+pub use decoding_table::{decode_next, Decoder};
+pub use encoding_table::encode;
+pub use error::{
+    AssembleError, AssembleErrorKind, DecodeError, DecodeErrorKind, EncodeError, ExecError,
+    TraceError,
+};
+pub use instruction::{
+    EffectiveAddress, EffectiveAddressBase, Instruction, Mnemonic, Operand, Prefixes, Repeat, Width,
+};
+pub use registers::{Reg16, Reg8, Register, SegReg};
+pub use simulator::Simulator;
+pub use source::{ByteSource, MemorySource, ReadSource};
+
+/// Decodes the instruction starting at `offset`, or `None` once `offset` is past the end.
+pub fn decode_instruction(
+    buffer: &[u8],
+    offset: usize,
+) -> Option<Result<Instruction, DecodeError>> {
+    decode_next(&mut buffer.get(offset..)?, offset)
+}
+
+/// Decodes every instruction in `buffer`, back to back from offset 0.
+/// Stops at the first instruction that fails to decode.
+pub fn decode(buffer: &[u8]) -> Result<Vec<Instruction>, DecodeError> {
+    Decoder::new(buffer).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::labels::Labels;
+
+    #[test]
+    fn truncated_instruction_reports_start_offset() {
+        // mov cx, bx followed by the first half of mov cx, [bx + 2]
+        let error = decode(&[0x89, 0xd9, 0x8b, 0x4f]).unwrap_err();
+        assert_eq!(error.offset, 2);
+        assert_eq!(error.kind, DecodeErrorKind::Truncated);
+    }
+
+    #[test]
+    fn invalid_instruction_after_prefix_reports_prefix_offset() {
+        // es: lea ax, ax
+        let error = decode(&[0x90, 0x26, 0x8d, 0xc0]).unwrap_err();
+        assert_eq!(error.offset, 1);
+        assert_eq!(
+            error.kind,
+            DecodeErrorKind::InvalidModRm {
+                opcode: 0x8d,
+                modrm: 0xc0
+            }
+        );
+    }
+
+    #[test]
+    fn invalid_modrm_is_rejected() {
+        // lea with a register operand
+        let error = decode(&[0x8d, 0xc0]).unwrap_err();
+        assert_eq!(
+            error.kind,
+            DecodeErrorKind::InvalidModRm {
+                opcode: 0x8d,
+                modrm: 0xc0
+            }
+        );
+    }
+
+    #[test]
+    fn near_branch_targets_are_relative_to_the_next_instruction() {
+        // nop; call $-1 (back to the nop); jmp $+0x103 (wraps past 0xffff)
+        let instructions = decode(&[0x90, 0xe8, 0xfc, 0xff, 0xe9, 0x00, 0x01]).unwrap();
+        assert_eq!(instructions[1].operands, vec![Operand::Relative(-4)]);
+        assert_eq!(instructions[1].branch_target(), Some(0));
+        assert_eq!(instructions[1].to_string(), "CALL $-1");
+        assert_eq!(instructions[2].branch_target(), Some(0x107));
+        assert_eq!(instructions[2].to_string(), "JMP $+259");
+    }
+
+    #[test]
+    fn prefixes_attach_to_the_next_instruction() {
+        // lock xchg al, [bx]; mov al, es:[bx + si]; repne scasb
+        let instructions = decode(&[0xf0, 0x86, 0x07, 0x26, 0x8a, 0x00, 0xf2, 0xae]).unwrap();
+        let text: Vec<String> = instructions
+            .iter()
+            .map(|instruction| instruction.to_string())
+            .collect();
+        assert_eq!(
+            text,
+            ["LOCK XCHG AL, [BX]", "MOV AL, ES:[BX + SI]", "REPNE SCASB"]
+        );
+        assert_eq!(instructions[1].prefixes.segment, Some(SegReg::Es));
+        assert_eq!(instructions[2].prefixes.rep, Some(Repeat::Repne));
+        assert_eq!(
+            instructions
+                .iter()
+                .map(|instruction| instruction.length)
+                .collect::<Vec<_>>(),
+            [3, 3, 2]
+        );
+    }
+
+    #[test]
+    fn overrides_without_a_memory_operand_lead_the_instruction() {
+        // es movsb; rep es movsw; cs jmp back to its own prefix
+        let bytes = [0x26, 0xa4, 0xf3, 0x26, 0xa5, 0x2e, 0xeb, 0xfd];
+        let instructions = decode(&bytes).unwrap();
+        let labels = Labels::collect(&instructions);
+        let text: Vec<String> = instructions
+            .iter()
+            .map(|instruction| labels.render(instruction))
+            .collect();
+        assert_eq!(text, ["ES MOVSB", "REP ES MOVSW", "CS JMP label_0"]);
+        assert_eq!(
+            assemble(&format!("{}\nlabel_0:\n{}", text[..2].join("\n"), text[2])).unwrap(),
+            bytes
+        );
+    }
+
+    #[test]
+    fn streamed_decoding_matches_the_buffer() {
+        // mov cx, bx; mov cx, es:[bx + 2]; jne $-5; then a stray lea opcode ahead of mov cx, bx
+        let bytes = [
+            0x89, 0xd9, 0x26, 0x8b, 0x4f, 0x02, 0x75, 0xf9, 0x8d, 0x89, 0xd9,
+        ];
+        let mut decoder = Decoder::new(ReadSource::new(&bytes[..]));
+        let streamed: Vec<_> = decoder.by_ref().map_while(Result::ok).collect();
+        assert_eq!(streamed, decode(&bytes[..8]).unwrap());
+        assert_eq!(decoder.address(), 8);
+        assert_eq!(decoder.skip_byte(), Some(0x8d));
+        assert_eq!(decoder.next().unwrap().unwrap().to_string(), "MOV CX, BX");
+        assert!(decoder.next().is_none());
+    }
+}
@@ -0,0 +1,101 @@
+//! objdump-style listing of a disassembly: each instruction's address and raw bytes next to
+//! its text, so it is plain which bytes the decoder took for which instruction.
+//!
+//! ```text
+//! 0000:0012  8B 4F 02             MOV CX, [BX + 2]
+//! ```
+
+use std::fmt::Write;
+
+/// How listing lines are laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    /// Segment printed before each offset.
+    pub segment: u16,
+    /// Bytes shown per line. Longer instructions continue on lines of their own, with the
+    /// address of their first byte there and no text.
+    pub bytes_per_line: usize,
+}
+
+impl Default for Layout {
+    fn default() -> Layout {
+        // Enough for any instruction without prefixes
+        Layout {
+            segment: 0,
+            bytes_per_line: 6,
+        }
+    }
+}
+
+/// The listing of one instruction, or one byte emitted as data, at `address`. Continuation
+/// lines are separated by newlines; there is no trailing newline.
+pub fn line(layout: &Layout, address: usize, bytes: &[u8], text: &str) -> String {
+    let width = layout.bytes_per_line.max(1);
+    let mut listing = String::new();
+    // A line even with no bytes, so the text is never lost
+    let rows: Vec<&[u8]> = if bytes.is_empty() {
+        vec![&[]]
+    } else {
+        bytes.chunks(width).collect()
+    };
+    for (row, chunk) in rows.into_iter().enumerate() {
+        if row > 0 {
+            listing.push('\n');
+        }
+        // Offsets wrap within the segment, as execution does
+        let offset = address.wrapping_add(row * width) as u16;
+        write!(listing, "{:04X}:{:04X} ", layout.segment, offset).unwrap();
+        for byte in chunk {
+            write!(listing, " {:02X}", byte).unwrap();
+        }
+        if row == 0 {
+            let padding = (width - chunk.len()) * 3;
+            write!(listing, "{:padding$}    {}", "", text).unwrap();
+        }
+    }
+    listing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::labels::Labels;
+    use crate::Decoder;
+
+    #[test]
+    fn bytes_line_up_before_the_text() {
+        // mov cx, [bx + 2]; mov word [bp + di - 100], 0x1234
+        let bytes = [0x8b, 0x4f, 0x02, 0xc7, 0x83, 0x9c, 0xff, 0x34, 0x12];
+        let mut decoder = Decoder::at(&bytes[..], 0x12);
+        let layout = Layout {
+            segment: 0,
+            bytes_per_line: 4,
+        };
+        let mut lines = Vec::new();
+        while let Some(instruction) = decoder.next() {
+            let instruction = instruction.unwrap();
+            let text = Labels::default().render(&instruction);
+            lines.push(line(&layout, instruction.address, decoder.bytes(), &text));
+        }
+        assert_eq!(lines[0], "0000:0012  8B 4F 02       MOV CX, [BX + 2]");
+        assert_eq!(
+            lines[1],
+            concat!(
+                "0000:0015  C7 83 9C FF    MOV WORD [BP + DI - 100], 4660\n",
+                "0000:0019  34 12"
+            )
+        );
+    }
+
+    #[test]
+    fn segment_and_wrapping_offsets() {
+        let layout = Layout {
+            segment: 0x1000,
+            bytes_per_line: 1,
+        };
+        assert_eq!(
+            line(&layout, 0xffff, &[0x8b, 0x4f], "x"),
+            "1000:FFFF  8B    x\n1000:0000  4F"
+        );
+    }
+}
@@ -1,66 +1,368 @@
 use std::env;
-use std::fs::File;
+use std::error::Error;
+use std::fs::{self, File};
 use std::io::prelude::*;
-use std::io::BufReader;
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
 
 use env_logger::{Builder, Target};
-use log::{error, info, Level, LevelFilter};
+use log::{error, warn, LevelFilter};
 
-mod decoders;
-mod decoding_table;
-mod readers;
+use sim86rs::clocks::Cpu;
+use sim86rs::labels::Labels;
+use sim86rs::{
+    json, listing, trace, DecodeError, Decoder, Instruction, ReadSource, Simulator, TraceError,
+};
 
-use decoding_table::*;
+mod cli;
 
-fn main() {
-    Builder::new()
-        .target(Target::Stdout) // Output all logs to stdout
-        .filter_level(LevelFilter::Debug) // Set the minimum log level to Info
-        .format(|buf, record| match record.level() {
-            Level::Info => {
-                writeln!(buf, "{}", record.args())
-            }
-            _ => {
-                writeln!(buf, "; [{}] {}", record.level(), record.args())
-            }
-        }) // Custom message format
-        .init();
+use cli::{Command, Format, Options, Trace};
 
+/// Program output goes here; diagnostics go to the log, on stderr.
+type Output<'a> = &'a mut dyn Write;
+
+fn main() {
     // Get command line arguments
     let args: Vec<String> = env::args().collect();
+    let options = match cli::parse(&args[1..]) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, cli::usage(&args[0]));
+            std::process::exit(1);
+        }
+    };
+
+    // Warnings and errors by default, more with each -v; RUST_LOG overrides both
+    let level = match options.verbosity {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    Builder::new()
+        .target(Target::Stderr)
+        .filter_level(level)
+        .parse_default_env()
+        .format(|buf, record| writeln!(buf, "[{}] {}", record.level(), record.args()))
+        .init();
 
-    // Check if the correct number of arguments are provided
-    if args.len() < 2 {
-        error!("Usage: {} <file_path>", args[0]);
-        std::process::exit(1);
+    // Assembling writes a binary to its output path; everything else writes text there
+    let mut out: BufWriter<Box<dyn Write>> = match &options.output {
+        Some(path) if options.command != Command::Assemble => match File::create(path) {
+            Ok(file) => BufWriter::new(Box::new(file)),
+            Err(error) => {
+                error!("Error creating file {}: {}", path, error);
+                std::process::exit(1);
+            }
+        },
+        _ => BufWriter::new(Box::new(io::stdout().lock())),
+    };
+    let result = match run(&mut out, &options) {
+        Ok(success) => match out.flush() {
+            Ok(()) => Ok(success),
+            Err(error) => Err(error.into()),
+        },
+        failure => failure,
+    };
+    match result {
+        Ok(true) => {}
+        // diff found differences; the output says which
+        Ok(false) => std::process::exit(1),
+        Err(failure) => {
+            // A closed pipe (`sim86rs file | head`) is the reader's choice, not a failure
+            let broken_pipe = failure
+                .downcast_ref::<io::Error>()
+                .is_some_and(|error| error.kind() == io::ErrorKind::BrokenPipe);
+            if !broken_pipe {
+                // Whatever was printed before the failure is still worth having
+                let _ = out.flush();
+                error!("{}", failure);
+                std::process::exit(1);
+            }
+        }
     }
+}
 
-    // Read the file
-    let file_path = &args[1];
-    let file = match File::open(file_path) {
-        Ok(file) => file,
-        Err(error) => {
-            error!("Error opening file {}: {}", file_path, error);
-            std::process::exit(1);
+/// Runs the command. `Ok(false)` means it completed but the answer is "no", as when `diff`
+/// finds differences.
+fn run(out: Output, options: &Options) -> Result<bool, Box<dyn Error>> {
+    let file_path = &options.inputs[0];
+    match options.command {
+        Command::Disasm => {
+            let source = ReadSource::new(open(file_path, options)?);
+            disassemble(out, file_path, source, options)?;
         }
+        Command::Assemble => {
+            let output_path = options.output.as_deref().expect("assemble has an output");
+            let source = String::from_utf8_lossy(&read(file_path, options)?).into_owned();
+            let binary = sim86rs::assemble(&source)
+                .map_err(|assemble_error| format!("{}: {}", file_path, assemble_error))?;
+            fs::write(output_path, binary)
+                .map_err(|error| format!("Error writing file {}: {}", output_path, error))?;
+        }
+        Command::Exec | Command::Cycles => {
+            let buffer = read(file_path, options)?;
+            let name = Path::new(file_path)
+                .file_name()
+                .map_or(file_path.clone(), |name| {
+                    name.to_string_lossy().into_owned()
+                });
+            if options.command == Command::Exec {
+                simulate(out, &name, &buffer, options, None)?;
+                return Ok(true);
+            }
+            for (index, cpu) in options.cpus.iter().enumerate() {
+                if index > 0 {
+                    writeln!(out)?;
+                }
+                simulate(out, &name, &buffer, options, Some(*cpu))?;
+            }
+        }
+        Command::Diff => return diff(out, &options.inputs[0], &options.inputs[1], options),
+    }
+    Ok(true)
+}
+
+/// Opens `file_path`, or stdin for "-", positioned at the start offset and limited to the
+/// requested length.
+fn open(file_path: &str, options: &Options) -> Result<impl Read, Box<dyn Error>> {
+    let input: Box<dyn Read> = if file_path == "-" {
+        Box::new(io::stdin().lock())
+    } else {
+        let file = File::open(file_path)
+            .map_err(|error| format!("Error opening file {}: {}", file_path, error))?;
+        Box::new(file)
     };
-    info!("; {}", file_path);
-    info!("BITS 16");
+    let mut reader = BufReader::new(input);
+    io::copy(
+        &mut (&mut reader).take(options.start as u64),
+        &mut io::sink(),
+    )
+    .map_err(|error| format!("Error reading file {}: {}", file_path, error))?;
+    let length = options.length.map_or(u64::MAX, |length| length as u64);
+    Ok(reader.take(length))
+}
 
-    // Read the file contents into a byte buffer
-    let mut buf_reader = BufReader::new(file);
+/// Reads all of the selected part of `file_path`, for the commands that need it at once.
+fn read(file_path: &str, options: &Options) -> Result<Vec<u8>, Box<dyn Error>> {
     let mut buffer = Vec::new();
-    match buf_reader.read_to_end(&mut buffer) {
-        Ok(_) => {}
-        Err(error) => {
-            error!("Error reading file {}: {}", file_path, error);
-            std::process::exit(1);
+    open(file_path, options)?
+        .read_to_end(&mut buffer)
+        .map_err(|error| format!("Error reading file {}: {}", file_path, error))?;
+    Ok(buffer)
+}
+
+/// A decoded instruction with its raw bytes, or a byte that did not decode.
+enum Entry {
+    Instruction(Instruction, Vec<u8>),
+    Skipped(DecodeError, u8),
+}
+
+/// Decodes `source` from the load address, handing each entry to `emit` as soon as it is
+/// decoded. Stops at the first undecodable byte unless resyncing; that error is returned
+/// after the entries before it.
+fn decode_entries<R: Read>(
+    file_path: &str,
+    source: ReadSource<R>,
+    options: &Options,
+    mut emit: impl FnMut(Entry) -> io::Result<()>,
+) -> Result<Option<DecodeError>, Box<dyn Error>> {
+    let mut decoder = Decoder::at(source, options.load_address as usize);
+    let mut fatal = None;
+    while let Some(result) = decoder.next() {
+        let entry = match result {
+            Ok(instruction) => Entry::Instruction(instruction, decoder.bytes().to_vec()),
+            Err(decode_error) if options.resync => {
+                // Emit the offending byte as data and try again from the next one
+                warn!("{}", decode_error);
+                match decoder.skip_byte() {
+                    Some(byte) => Entry::Skipped(decode_error, byte),
+                    None => continue,
+                }
+            }
+            Err(decode_error) => {
+                fatal = Some(decode_error);
+                break;
+            }
+        };
+        emit(entry)?;
+    }
+    if let Some(error) = decoder.into_source().error() {
+        return Err(format!("Error reading file {}: {}", file_path, error).into());
+    }
+    Ok(fatal)
+}
+
+fn branch_labels(entries: &[Entry]) -> Labels {
+    let instructions: Vec<_> = entries
+        .iter()
+        .filter_map(|entry| match entry {
+            Entry::Instruction(instruction, _) => Some(instruction.clone()),
+            Entry::Skipped(..) => None,
+        })
+        .collect();
+    Labels::collect(&instructions)
+}
+
+fn disassemble<R: Read>(
+    out: Output,
+    file_path: &str,
+    source: ReadSource<R>,
+    options: &Options,
+) -> Result<(), Box<dyn Error>> {
+    let format = options.format;
+    if format == Format::Text {
+        writeln!(out, "; {}", file_path)?;
+        writeln!(out, "BITS 16")?;
+    }
+
+    // Without labels every line can be printed as soon as it is decoded. With them,
+    // everything is decoded first, so branch targets are known before printing.
+    let mut decoded = Vec::new();
+    let fatal = decode_entries(file_path, source, options, |entry| {
+        if options.labels {
+            decoded.push(entry);
+            Ok(())
+        } else {
+            print_entry(out, &entry, &Labels::default(), format)
         }
-    };
+    })?;
+    if options.labels {
+        let labels = branch_labels(&decoded);
+        for entry in &decoded {
+            print_entry(out, entry, &labels, format)?;
+        }
+    }
 
-    let mut iterator = buffer.iter();
+    if let Some(decode_error) = fatal {
+        if format == Format::Json {
+            writeln!(out, "{}", json::error(&decode_error))?;
+        }
+        return Err(decode_error.into());
+    }
+    Ok(())
+}
 
-    while let Some(byte) = iterator.next() {
-        decode_first_byte(*byte, &mut iterator);
+/// Prints one decoded instruction, with a label line ahead of it if it is a branch target,
+/// or a byte that could not be decoded as data.
+fn print_entry(out: Output, entry: &Entry, labels: &Labels, format: Format) -> io::Result<()> {
+    match (entry, format) {
+        (Entry::Instruction(instruction, _), Format::Text) => {
+            if let Some(label) = labels.at(instruction.address) {
+                writeln!(out, "{}:", label)?;
+            }
+            writeln!(out, "{}", labels.render(instruction))
+        }
+        (Entry::Instruction(instruction, bytes), Format::Json) => {
+            writeln!(out, "{}", json::instruction(instruction, bytes, labels))
+        }
+        (Entry::Instruction(instruction, bytes), Format::Listing(layout)) => {
+            if let Some(label) = labels.at(instruction.address) {
+                writeln!(out, "{}:", label)?;
+            }
+            let text = labels.render(instruction);
+            writeln!(
+                out,
+                "{}",
+                listing::line(&layout, instruction.address, bytes, &text)
+            )
+        }
+        (Entry::Skipped(_, byte), Format::Text) => writeln!(out, "db 0x{:02x}", byte),
+        (Entry::Skipped(error, byte), Format::Listing(layout)) => {
+            let text = format!("db 0x{:02x}", byte);
+            writeln!(
+                out,
+                "{}",
+                listing::line(&layout, error.offset, &[*byte], &text)
+            )
+        }
+        (Entry::Skipped(error, byte), Format::Json) => {
+            writeln!(out, "{}", json::skipped(error, *byte))
+        }
     }
 }
+
+/// Disassembles both files and prints every address where they disagree, `-` for the
+/// first file and `+` for the second. Returns whether they agree.
+fn diff(out: Output, first: &str, second: &str, options: &Options) -> Result<bool, Box<dyn Error>> {
+    let listing = |file_path: &str| -> Result<Vec<(usize, String)>, Box<dyn Error>> {
+        let mut entries = Vec::new();
+        let source = ReadSource::new(open(file_path, options)?);
+        if let Some(decode_error) = decode_entries(file_path, source, options, |entry| {
+            entries.push(entry);
+            Ok(())
+        })? {
+            return Err(format!("{}: {}", file_path, decode_error).into());
+        }
+        let labels = if options.labels {
+            branch_labels(&entries)
+        } else {
+            Labels::default()
+        };
+        Ok(entries
+            .iter()
+            .map(|entry| match entry {
+                Entry::Instruction(instruction, _) => {
+                    (instruction.address, labels.render(instruction))
+                }
+                Entry::Skipped(error, byte) => (error.offset, format!("db 0x{:02x}", byte)),
+            })
+            .collect())
+    };
+    let (first_lines, second_lines) = (listing(first)?, listing(second)?);
+
+    // Both listings are in address order, so walk them together like a merge
+    let mut same = true;
+    let (mut a, mut b) = (
+        first_lines.iter().peekable(),
+        second_lines.iter().peekable(),
+    );
+    loop {
+        let (removed, added) = match (a.peek(), b.peek()) {
+            (None, None) => break,
+            (Some(x), Some(y)) if x == y => {
+                a.next();
+                b.next();
+                continue;
+            }
+            (Some(x), Some(y)) if x.0 == y.0 => (a.next(), b.next()),
+            (Some(x), Some(y)) if x.0 < y.0 => (a.next(), None),
+            (Some(_), None) => (a.next(), None),
+            _ => (None, b.next()),
+        };
+        if same {
+            // Identical files print nothing, as with diff(1)
+            writeln!(out, "--- {}", first)?;
+            writeln!(out, "+++ {}", second)?;
+            same = false;
+        }
+        if let Some((address, text)) = removed {
+            writeln!(out, "-{:04x}: {}", address, text)?;
+        }
+        if let Some((address, text)) = added {
+            writeln!(out, "+{:04x}: {}", address, text)?;
+        }
+    }
+    Ok(same)
+}
+
+fn simulate(
+    out: Output,
+    name: &str,
+    buffer: &[u8],
+    options: &Options,
+    cpu: Option<Cpu>,
+) -> Result<(), Box<dyn Error>> {
+    let mut simulator = Simulator::new();
+    simulator.load_at(options.load_address, buffer);
+    let trace_options = trace::Options {
+        cpu,
+        show_ip: options.trace != Trace::NoIp,
+        steps: options.trace != Trace::Final,
+    };
+    trace::run(&mut simulator, name, trace_options, out).map_err(|error| match error {
+        // Kept as an io::Error so a broken pipe is still recognised
+        TraceError::Io(error) => error.into(),
+        error => error.into(),
+    })
+}
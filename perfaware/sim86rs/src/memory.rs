@@ -0,0 +1,86 @@
+/// Size of the 8086's 20-bit physical address space.
+pub const MEMORY_SIZE: usize = 1 << 20;
+
+const ADDRESS_MASK: u32 = (MEMORY_SIZE as u32) - 1;
+
+/// Translates `segment:offset` to a physical address, wrapping at 1 MiB like the 8086 does.
+pub fn physical_address(segment: u16, offset: u16) -> u32 {
+    (((segment as u32) << 4) + offset as u32) & ADDRESS_MASK
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Memory {
+    bytes: Vec<u8>,
+}
+
+impl Memory {
+    pub fn new() -> Memory {
+        Memory {
+            bytes: vec![0; MEMORY_SIZE],
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn read_byte(&self, address: u32) -> u8 {
+        self.bytes[(address & ADDRESS_MASK) as usize]
+    }
+
+    pub fn write_byte(&mut self, address: u32, value: u8) {
+        self.bytes[(address & ADDRESS_MASK) as usize] = value;
+    }
+
+    /// Reads a little-endian word. The high byte wraps around the end of memory.
+    pub fn read_word(&self, address: u32) -> u16 {
+        let lo = self.read_byte(address) as u16;
+        let hi = self.read_byte(address.wrapping_add(1)) as u16;
+        hi << 8 | lo
+    }
+
+    pub fn write_word(&mut self, address: u32, value: u16) {
+        self.write_byte(address, value as u8);
+        self.write_byte(address.wrapping_add(1), (value >> 8) as u8);
+    }
+
+    /// Copies `data` into memory starting at `address`.
+    pub fn load(&mut self, address: u32, data: &[u8]) {
+        for (i, byte) in data.iter().enumerate() {
+            self.write_byte(address.wrapping_add(i as u32), *byte);
+        }
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Memory {
+        Memory::new()
+    }
+}
+
+impl std::fmt::Debug for Memory {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // A megabyte of bytes is not useful debug output
+        write!(f, "Memory({} bytes)", self.bytes.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn physical_address_wraps_at_one_megabyte() {
+        assert_eq!(physical_address(0x1234, 0x0010), 0x12350);
+        assert_eq!(physical_address(0xFFFF, 0x0010), 0x00000);
+    }
+
+    #[test]
+    fn words_are_little_endian() {
+        let mut memory = Memory::new();
+        memory.write_word(0x1000, 0xBEEF);
+        assert_eq!(memory.read_byte(0x1000), 0xEF);
+        assert_eq!(memory.read_byte(0x1001), 0xBE);
+        assert_eq!(memory.read_word(0x1000), 0xBEEF);
+    }
+}
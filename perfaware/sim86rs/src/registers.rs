@@ -0,0 +1,269 @@
+use std::fmt;
+
+/// Register names in the order they are stored and dumped.
+pub const REGISTER_NAMES: [&str; 13] = [
+    "AX", "BX", "CX", "DX", "SP", "BP", "SI", "DI", "ES", "CS", "SS", "DS", "IP",
+];
+
+pub const IP: usize = 12;
+
+macro_rules! register_enum {
+    ($(#[$meta:meta])* $name:ident { $($variant:ident => $text:literal,)* }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum $name {
+            $($variant,)*
+        }
+
+        impl $name {
+            /// Every register, in encoding order.
+            pub const ALL: &'static [$name] = &[$($name::$variant,)*];
+
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $($name::$variant => $text,)*
+                }
+            }
+
+            /// The register a REG, R/M or SR field selects.
+            pub fn from_field(field: u8) -> $name {
+                $name::ALL[field as usize % $name::ALL.len()]
+            }
+
+            /// The REG, R/M or SR field that selects this register.
+            pub fn field(&self) -> u8 {
+                *self as u8
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}", self.name())
+            }
+        }
+    };
+}
+
+register_enum! {
+    /// Byte registers, the halves of AX..BX.
+    Reg8 {
+        Al => "AL",
+        Cl => "CL",
+        Dl => "DL",
+        Bl => "BL",
+        Ah => "AH",
+        Ch => "CH",
+        Dh => "DH",
+        Bh => "BH",
+    }
+}
+
+register_enum! {
+    Reg16 {
+        Ax => "AX",
+        Cx => "CX",
+        Dx => "DX",
+        Bx => "BX",
+        Sp => "SP",
+        Bp => "BP",
+        Si => "SI",
+        Di => "DI",
+    }
+}
+
+register_enum! {
+    SegReg {
+        Es => "ES",
+        Cs => "CS",
+        Ss => "SS",
+        Ds => "DS",
+    }
+}
+
+/// Any register an instruction can name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Register {
+    Byte(Reg8),
+    Word(Reg16),
+    Segment(SegReg),
+}
+
+impl Register {
+    /// The general register a REG or R/M field selects at the given W.
+    pub fn from_field(field: u8, w_field: u8) -> Register {
+        if w_field == 0b1 {
+            Register::Word(Reg16::from_field(field))
+        } else {
+            Register::Byte(Reg8::from_field(field))
+        }
+    }
+
+    /// The register spelled `name`, in any case.
+    pub fn from_name(name: &str) -> Option<Register> {
+        let name = name.to_ascii_uppercase();
+        let byte = Reg8::ALL.iter().map(|&reg| Register::Byte(reg));
+        let word = Reg16::ALL.iter().map(|&reg| Register::Word(reg));
+        let segment = SegReg::ALL.iter().map(|&reg| Register::Segment(reg));
+        byte.chain(word)
+            .chain(segment)
+            .find(|register| register.to_string() == name)
+    }
+
+    /// Slot in the register file, and which byte of it for the byte registers.
+    fn location(&self) -> (usize, Part) {
+        // Reg16 encoding order is AX CX DX BX; the file stores AX BX CX DX
+        const GENERAL_SLOTS: [usize; 4] = [0, 2, 3, 1];
+        match *self {
+            Register::Byte(reg) => {
+                let index = reg as usize;
+                let part = if index < 4 { Part::Low } else { Part::High };
+                (GENERAL_SLOTS[index % 4], part)
+            }
+            Register::Word(reg) => match reg as usize {
+                index @ 0..=3 => (GENERAL_SLOTS[index], Part::Whole),
+                index => (index, Part::Whole),
+            },
+            Register::Segment(reg) => (8 + reg as usize, Part::Whole),
+        }
+    }
+}
+
+impl From<Reg8> for Register {
+    fn from(reg: Reg8) -> Register {
+        Register::Byte(reg)
+    }
+}
+
+impl From<Reg16> for Register {
+    fn from(reg: Reg16) -> Register {
+        Register::Word(reg)
+    }
+}
+
+impl From<SegReg> for Register {
+    fn from(reg: SegReg) -> Register {
+        Register::Segment(reg)
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Register::Byte(reg) => write!(f, "{}", reg),
+            Register::Word(reg) => write!(f, "{}", reg),
+            Register::Segment(reg) => write!(f, "{}", reg),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Part {
+    Low,
+    High,
+    Whole,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RegisterFile {
+    values: [u16; 13],
+}
+
+impl RegisterFile {
+    pub fn get(&self, index: usize) -> u16 {
+        self.values[index]
+    }
+
+    pub fn set(&mut self, index: usize, value: u16) {
+        self.values[index] = value;
+    }
+
+    /// Index of the 16-bit register that holds `register`, e.g. `AX` for `AH`.
+    pub fn index_of(register: impl Into<Register>) -> usize {
+        register.into().location().0
+    }
+
+    pub fn read(&self, register: impl Into<Register>) -> u16 {
+        let (index, part) = register.into().location();
+        let value = self.values[index];
+        match part {
+            Part::Low => value & 0x00FF,
+            Part::High => value >> 8,
+            Part::Whole => value,
+        }
+    }
+
+    pub fn write(&mut self, register: impl Into<Register>, value: u16) {
+        let (index, part) = register.into().location();
+        let old = self.values[index];
+        self.values[index] = match part {
+            Part::Low => (old & 0xFF00) | (value & 0x00FF),
+            Part::High => (old & 0x00FF) | (value << 8),
+            Part::Whole => value,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Flags(pub u16);
+
+impl Flags {
+    pub const CARRY: u16 = 0x0001;
+    pub const PARITY: u16 = 0x0004;
+    pub const AUXILIARY_CARRY: u16 = 0x0010;
+    pub const ZERO: u16 = 0x0040;
+    pub const SIGN: u16 = 0x0080;
+    pub const TRAP: u16 = 0x0100;
+    pub const INTERRUPT: u16 = 0x0200;
+    pub const DIRECTION: u16 = 0x0400;
+    pub const OVERFLOW: u16 = 0x0800;
+
+    pub fn get(&self, flag: u16) -> bool {
+        self.0 & flag != 0
+    }
+
+    pub fn set(&mut self, flag: u16, value: bool) {
+        if value {
+            self.0 |= flag;
+        } else {
+            self.0 &= !flag;
+        }
+    }
+}
+
+impl fmt::Display for Flags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const LETTERS: [(u16, char); 9] = [
+            (Flags::CARRY, 'C'),
+            (Flags::PARITY, 'P'),
+            (Flags::AUXILIARY_CARRY, 'A'),
+            (Flags::ZERO, 'Z'),
+            (Flags::SIGN, 'S'),
+            (Flags::TRAP, 'T'),
+            (Flags::INTERRUPT, 'I'),
+            (Flags::DIRECTION, 'D'),
+            (Flags::OVERFLOW, 'O'),
+        ];
+        for (flag, letter) in LETTERS {
+            if self.get(flag) {
+                write!(f, "{}", letter)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_registers_alias_word_registers() {
+        let mut registers = RegisterFile::default();
+        registers.write(Reg16::Bx, 0x1234);
+        registers.write(Reg8::Bh, 0xAB);
+        assert_eq!(registers.read(Reg8::Bl), 0x34);
+        assert_eq!(registers.read(Reg16::Bx), 0xAB34);
+        assert_eq!(REGISTER_NAMES[RegisterFile::index_of(Reg8::Bh)], "BX");
+        assert_eq!(REGISTER_NAMES[RegisterFile::index_of(SegReg::Ds)], "DS");
+    }
+}
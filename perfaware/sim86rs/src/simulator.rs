@@ -0,0 +1,402 @@
+use log::debug;
+
+use crate::decoding_table::decode_next;
+use crate::error::ExecError;
+use crate::instruction::{EffectiveAddress, Instruction, Mnemonic, Operand, Width};
+use crate::memory::{physical_address, Memory};
+use crate::registers::{Flags, Reg16, RegisterFile, SegReg, IP};
+use crate::source::MemorySource;
+
+/// Register and flag state at one point in time, used to report what an instruction changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    pub registers: RegisterFile,
+    pub flags: Flags,
+}
+
+/// How many instructions a program may execute before it is taken to be stuck, as in
+/// `jmp $`.
+pub const STEP_LIMIT: usize = 1_000_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Simulator {
+    pub registers: RegisterFile,
+    pub flags: Flags,
+    pub memory: Memory,
+    /// Instructions to execute at most before [`Simulator::step`] fails.
+    pub step_limit: usize,
+    /// Offset just past the loaded program; execution stops once IP reaches it.
+    program_end: usize,
+    /// Instructions executed since the program was loaded.
+    steps: usize,
+    /// Set by HLT; execution stops there too.
+    halted: bool,
+}
+
+impl Default for Simulator {
+    fn default() -> Simulator {
+        Simulator {
+            registers: RegisterFile::default(),
+            flags: Flags::default(),
+            memory: Memory::default(),
+            step_limit: STEP_LIMIT,
+            program_end: 0,
+            steps: 0,
+            halted: false,
+        }
+    }
+}
+
+impl Simulator {
+    pub fn new() -> Simulator {
+        Simulator::default()
+    }
+
+    pub fn ip(&self) -> usize {
+        self.registers.get(IP) as usize
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            registers: self.registers,
+            flags: self.flags,
+        }
+    }
+
+    /// Copies `program` to CS:0000, where execution starts.
+    pub fn load(&mut self, program: &[u8]) {
+        self.load_at(0, program);
+    }
+
+    /// Copies `program` to CS:`offset` and points IP at it.
+    pub fn load_at(&mut self, offset: u16, program: &[u8]) {
+        let cs = self.registers.read(SegReg::Cs);
+        self.memory.load(physical_address(cs, offset), program);
+        self.registers.set(IP, offset);
+        self.program_end = offset as usize + program.len();
+        self.steps = 0;
+        self.halted = false;
+    }
+
+    /// Decodes and executes the instruction at CS:IP, or returns `None` once IP runs off the end of the program
+    /// or a HLT has executed. Fails once `step_limit` instructions have run without either.
+    pub fn step(&mut self) -> Option<Result<Instruction, ExecError>> {
+        if self.halted {
+            return None;
+        }
+        if self.steps >= self.step_limit {
+            return Some(Err(ExecError::StepLimit(self.step_limit)));
+        }
+        let mut code = MemorySource {
+            memory: &self.memory,
+            segment: self.registers.read(SegReg::Cs),
+            offset: self.ip() as u16,
+            remaining: self.program_end.checked_sub(self.ip())?,
+        };
+        let instruction = match decode_next(&mut code, self.ip())? {
+            Ok(instruction) => instruction,
+            Err(error) => return Some(Err(error.into())),
+        };
+        self.steps += 1;
+        Some(self.execute(&instruction).map(|_| instruction))
+    }
+
+    /// Executes one decoded instruction. IP is advanced past it first, so jumps are relative to its end.
+    pub fn execute(&mut self, instruction: &Instruction) -> Result<(), ExecError> {
+        debug!("Execute: {}", instruction);
+        let next_ip = (instruction.address + instruction.length) as u16;
+        self.registers.set(IP, next_ip);
+
+        let width = instruction.width.unwrap_or(Width::Word);
+        match (instruction.mnemonic, instruction.operands.as_slice()) {
+            (Mnemonic::Mov, [destination, source]) => {
+                let value = self.read(instruction, source)?;
+                self.write(instruction, destination, value)?;
+            }
+            (
+                Mnemonic::Add
+                | Mnemonic::Adc
+                | Mnemonic::Sub
+                | Mnemonic::Sbb
+                | Mnemonic::Cmp
+                | Mnemonic::And
+                | Mnemonic::Or
+                | Mnemonic::Xor
+                | Mnemonic::Test,
+                [destination, source],
+            ) => {
+                let a = self.read(instruction, destination)?;
+                let b = self.read(instruction, source)?;
+                let result = self.arithmetic(instruction.mnemonic, a, b, width);
+                if ![Mnemonic::Cmp, Mnemonic::Test].contains(&instruction.mnemonic) {
+                    self.write(instruction, destination, result)?;
+                }
+            }
+            (Mnemonic::Inc | Mnemonic::Dec, [destination]) => {
+                let a = self.read(instruction, destination)?;
+                // INC and DEC leave the carry flag alone
+                let carry = self.flags.get(Flags::CARRY);
+                let op = if instruction.mnemonic == Mnemonic::Inc {
+                    Mnemonic::Add
+                } else {
+                    Mnemonic::Sub
+                };
+                let result = self.arithmetic(op, a, 1, width);
+                self.flags.set(Flags::CARRY, carry);
+                self.write(instruction, destination, result)?;
+            }
+            (Mnemonic::Neg, [destination]) => {
+                let a = self.read(instruction, destination)?;
+                let result = self.arithmetic(Mnemonic::Sub, 0, a, width);
+                self.write(instruction, destination, result)?;
+            }
+            (Mnemonic::Hlt, []) => self.halted = true,
+            (mnemonic, [Operand::Relative(_)]) => {
+                if self
+                    .branch_taken(mnemonic)
+                    .ok_or_else(|| unsupported(instruction))?
+                {
+                    let target = instruction.branch_target().unwrap();
+                    self.registers.set(IP, target);
+                }
+            }
+            _ => return Err(unsupported(instruction)),
+        }
+        Ok(())
+    }
+
+    fn read(&self, instruction: &Instruction, operand: &Operand) -> Result<u16, ExecError> {
+        match operand {
+            Operand::Register(register) => Ok(self.registers.read(*register)),
+            Operand::Immediate(value) => Ok(*value as u16),
+            Operand::Memory(address) => {
+                let address = self.physical_address(instruction, address);
+                Ok(match instruction.width {
+                    Some(Width::Byte) => self.memory.read_byte(address) as u16,
+                    _ => self.memory.read_word(address),
+                })
+            }
+            _ => Err(unsupported(instruction)),
+        }
+    }
+
+    fn write(
+        &mut self,
+        instruction: &Instruction,
+        operand: &Operand,
+        value: u16,
+    ) -> Result<(), ExecError> {
+        match operand {
+            Operand::Register(register) => {
+                self.registers.write(*register, value);
+                Ok(())
+            }
+            Operand::Memory(address) => {
+                let address = self.physical_address(instruction, address);
+                match instruction.width {
+                    Some(Width::Byte) => self.memory.write_byte(address, value as u8),
+                    _ => self.memory.write_word(address, value),
+                }
+                Ok(())
+            }
+            _ => Err(unsupported(instruction)),
+        }
+    }
+
+    /// Resolves a memory operand to a physical address. BP-based addressing defaults to SS,
+    /// everything else to DS, unless the instruction carries a segment override.
+    fn physical_address(&self, instruction: &Instruction, address: &EffectiveAddress) -> u32 {
+        let segment = address
+            .base
+            .map_or(SegReg::Ds, |base| base.default_segment());
+        let segment = instruction.prefixes.segment.unwrap_or(segment);
+        physical_address(
+            self.registers.read(segment),
+            effective_offset(&self.registers, address),
+        )
+    }
+
+    /// Computes `a op b` at the given width and updates the arithmetic flags.
+    fn arithmetic(&mut self, op: Mnemonic, a: u16, b: u16, width: Width) -> u16 {
+        let (mask, sign) = match width {
+            Width::Byte => (0x00FF_u32, 0x0080_u32),
+            Width::Word => (0xFFFF_u32, 0x8000_u32),
+        };
+        let (a, b) = (a as u32 & mask, b as u32 & mask);
+        let carry_in = match op {
+            Mnemonic::Adc | Mnemonic::Sbb => self.flags.get(Flags::CARRY) as u32,
+            _ => 0,
+        };
+
+        let result = match op {
+            Mnemonic::Add | Mnemonic::Adc => {
+                let wide = a + b + carry_in;
+                let result = wide & mask;
+                self.flags.set(Flags::CARRY, wide > mask);
+                self.flags
+                    .set(Flags::AUXILIARY_CARRY, (a ^ b ^ result) & 0x10 != 0);
+                self.flags
+                    .set(Flags::OVERFLOW, (a ^ result) & (b ^ result) & sign != 0);
+                result
+            }
+            Mnemonic::Sub | Mnemonic::Sbb | Mnemonic::Cmp => {
+                let result = a.wrapping_sub(b).wrapping_sub(carry_in) & mask;
+                self.flags.set(Flags::CARRY, b + carry_in > a);
+                self.flags
+                    .set(Flags::AUXILIARY_CARRY, (a ^ b ^ result) & 0x10 != 0);
+                self.flags
+                    .set(Flags::OVERFLOW, (a ^ b) & (a ^ result) & sign != 0);
+                result
+            }
+            _ => {
+                let result = match op {
+                    Mnemonic::Or => a | b,
+                    Mnemonic::Xor => a ^ b,
+                    _ => a & b,
+                };
+                self.flags.set(Flags::CARRY, false);
+                self.flags.set(Flags::AUXILIARY_CARRY, false);
+                self.flags.set(Flags::OVERFLOW, false);
+                result
+            }
+        };
+
+        self.flags.set(Flags::ZERO, result == 0);
+        self.flags.set(Flags::SIGN, result & sign != 0);
+        self.flags
+            .set(Flags::PARITY, (result & 0xFF).count_ones() % 2 == 0);
+        result as u16
+    }
+
+    /// Evaluates the condition of a relative jump, decrementing CX for the LOOP family.
+    fn branch_taken(&mut self, mnemonic: Mnemonic) -> Option<bool> {
+        let flags = self.flags;
+        let carry = flags.get(Flags::CARRY);
+        let zero = flags.get(Flags::ZERO);
+        let sign = flags.get(Flags::SIGN);
+        let overflow = flags.get(Flags::OVERFLOW);
+        let parity = flags.get(Flags::PARITY);
+        Some(match mnemonic {
+            Mnemonic::Jmp => true,
+            Mnemonic::Je => zero,
+            Mnemonic::Jne => !zero,
+            Mnemonic::Jl => sign != overflow,
+            Mnemonic::Jnl => sign == overflow,
+            Mnemonic::Jle => zero || sign != overflow,
+            Mnemonic::Jg => !zero && sign == overflow,
+            Mnemonic::Jb => carry,
+            Mnemonic::Jnb => !carry,
+            Mnemonic::Jbe => carry || zero,
+            Mnemonic::Ja => !carry && !zero,
+            Mnemonic::Jp => parity,
+            Mnemonic::Jnp => !parity,
+            Mnemonic::Jo => overflow,
+            Mnemonic::Jno => !overflow,
+            Mnemonic::Js => sign,
+            Mnemonic::Jns => !sign,
+            Mnemonic::Jcxz => self.registers.read(Reg16::Cx) == 0,
+            Mnemonic::Loop | Mnemonic::Loopz | Mnemonic::Loopnz => {
+                let cx = self.registers.read(Reg16::Cx).wrapping_sub(1);
+                self.registers.write(Reg16::Cx, cx);
+                match mnemonic {
+                    Mnemonic::Loopz => cx != 0 && zero,
+                    Mnemonic::Loopnz => cx != 0 && !zero,
+                    _ => cx != 0,
+                }
+            }
+            _ => return None,
+        })
+    }
+}
+
+/// The offset within its segment that a memory operand refers to, given the registers.
+pub fn effective_offset(registers: &RegisterFile, address: &EffectiveAddress) -> u16 {
+    let mut offset = address.displacement.unwrap_or(0) as u16;
+    if let Some(base) = address.base {
+        for register in base.registers() {
+            offset = offset.wrapping_add(registers.read(*register));
+        }
+    }
+    offset
+}
+
+fn unsupported(instruction: &Instruction) -> ExecError {
+    ExecError::Unsupported {
+        address: instruction.address,
+        instruction: instruction.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(program: &[u8]) -> Simulator {
+        let mut simulator = Simulator::new();
+        simulator.load(program);
+        while let Some(result) = simulator.step() {
+            result.unwrap();
+        }
+        simulator
+    }
+
+    #[test]
+    fn cmp_sets_flags_without_writing() {
+        // mov sp, 99; mov bp, 98; cmp bp, sp
+        let simulator = run(&[0xbc, 0x63, 0x00, 0xbd, 0x62, 0x00, 0x39, 0xe5]);
+        assert_eq!(simulator.registers.read(Reg16::Bp), 98);
+        assert_eq!(simulator.flags.to_string(), "CPAS");
+    }
+
+    #[test]
+    fn loop_runs_until_cx_is_zero() {
+        // mov cx, 3; add bx, 10; loop $-3
+        let simulator = run(&[0xb9, 0x03, 0x00, 0x83, 0xc3, 0x0a, 0xe2, 0xfb]);
+        assert_eq!(simulator.registers.read(Reg16::Bx), 30);
+        assert_eq!(simulator.registers.read(Reg16::Cx), 0);
+        assert_eq!(simulator.ip(), 8);
+    }
+
+    #[test]
+    fn programs_run_from_their_load_offset() {
+        // mov cx, 3; add bx, 10; loop $-3, at CS:0100
+        let mut simulator = Simulator::new();
+        simulator.load_at(0x100, &[0xb9, 0x03, 0x00, 0x83, 0xc3, 0x0a, 0xe2, 0xfb]);
+        while let Some(result) = simulator.step() {
+            result.unwrap();
+        }
+        assert_eq!(simulator.registers.read(Reg16::Bx), 30);
+        assert_eq!(simulator.ip(), 0x108);
+    }
+
+    #[test]
+    fn hlt_ends_the_program() {
+        // mov cx, 3; hlt; mov cx, 4
+        let simulator = run(&[0xb9, 0x03, 0x00, 0xf4, 0xb9, 0x04, 0x00]);
+        assert_eq!(simulator.registers.read(Reg16::Cx), 3);
+        assert_eq!(simulator.ip(), 4);
+    }
+
+    #[test]
+    fn endless_loops_hit_the_step_limit() {
+        // jmp $
+        let mut simulator = Simulator::new();
+        simulator.step_limit = 10;
+        simulator.load(&[0xeb, 0xfe]);
+        for _ in 0..10 {
+            simulator.step().unwrap().unwrap();
+        }
+        assert_eq!(simulator.step(), Some(Err(ExecError::StepLimit(10))));
+    }
+
+    #[test]
+    fn bp_addressing_defaults_to_ss() {
+        // mov ax, 0x100; mov ss, ax; mov bp, 4; mov word [bp + 0], 7; mov bx, 4; mov word es:[bx], 9
+        let simulator = run(&[
+            0xb8, 0x00, 0x01, 0x8e, 0xd0, 0xbd, 0x04, 0x00, 0xc7, 0x46, 0x00, 0x07, 0x00, 0xbb,
+            0x04, 0x00, 0x26, 0xc7, 0x07, 0x09, 0x00,
+        ]);
+        assert_eq!(simulator.memory.read_word(0x1004), 7);
+        assert_eq!(simulator.memory.read_word(0x0004), 9);
+    }
+}
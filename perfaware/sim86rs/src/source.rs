@@ -0,0 +1,155 @@
+//! Where the decoder gets its bytes from. The decoder tries table entries one after another,
+//! so a source must let it look ahead of the current position before committing to an
+//! instruction; only then are the bytes consumed.
+
+use std::collections::VecDeque;
+use std::io::{self, Read};
+
+use crate::memory::{physical_address, Memory};
+
+pub trait ByteSource {
+    /// The byte `index` bytes past the current position, or `None` if the input ends first.
+    fn peek(&mut self, index: usize) -> Option<u8>;
+
+    /// Moves the current position `count` bytes forward. `count` never exceeds what `peek`
+    /// has already returned.
+    fn consume(&mut self, count: usize);
+}
+
+/// A slice, or anything that derefs to one, such as a memory-mapped file.
+impl ByteSource for &[u8] {
+    fn peek(&mut self, index: usize) -> Option<u8> {
+        self.get(index).copied()
+    }
+
+    fn consume(&mut self, count: usize) {
+        *self = &self[count..];
+    }
+}
+
+/// Streams bytes from a reader, such as a `BufReader` over a file or stdin, buffering only
+/// the lookahead the decoder asks for.
+///
+/// A read error ends the input early; check [`ReadSource::error`] once decoding stops.
+#[derive(Debug)]
+pub struct ReadSource<R> {
+    reader: R,
+    lookahead: VecDeque<u8>,
+    error: Option<io::Error>,
+}
+
+impl<R: Read> ReadSource<R> {
+    pub fn new(reader: R) -> ReadSource<R> {
+        ReadSource {
+            reader,
+            lookahead: VecDeque::new(),
+            error: None,
+        }
+    }
+
+    /// The error that ended the input, if it did not simply run out.
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    /// Reads until there are more than `index` bytes of lookahead, or the input ends.
+    fn fill(&mut self, index: usize) {
+        let mut chunk = [0; 64];
+        while self.lookahead.len() <= index && self.error.is_none() {
+            match self.reader.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(count) => self.lookahead.extend(&chunk[..count]),
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(error) => self.error = Some(error),
+            }
+        }
+    }
+}
+
+impl<R: Read> ByteSource for ReadSource<R> {
+    fn peek(&mut self, index: usize) -> Option<u8> {
+        self.fill(index);
+        self.lookahead.get(index).copied()
+    }
+
+    fn consume(&mut self, count: usize) {
+        self.lookahead.drain(..count);
+    }
+}
+
+/// Reads code out of simulated memory at `segment:offset`, wrapping within the segment.
+/// `remaining` bounds the input, so decoding stops at the end of the loaded program.
+#[derive(Debug, Clone, Copy)]
+pub struct MemorySource<'a> {
+    pub memory: &'a Memory,
+    pub segment: u16,
+    pub offset: u16,
+    pub remaining: usize,
+}
+
+impl ByteSource for MemorySource<'_> {
+    fn peek(&mut self, index: usize) -> Option<u8> {
+        (index < self.remaining).then(|| {
+            let offset = self.offset.wrapping_add(index as u16);
+            self.memory
+                .read_byte(physical_address(self.segment, offset))
+        })
+    }
+
+    fn consume(&mut self, count: usize) {
+        self.offset = self.offset.wrapping_add(count as u16);
+        self.remaining -= count;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hands out one byte per read, like a slow pipe.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.split_first() {
+                Some((byte, rest)) if !buf.is_empty() => {
+                    buf[0] = *byte;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn reader_matches_slice() {
+        let bytes = [0x89, 0xd9, 0x8b, 0x4f, 0x02];
+        let mut slice = &bytes[..];
+        let mut reader = ReadSource::new(Trickle(&bytes));
+        for (skip, index) in [(0, 4), (2, 0), (0, 2), (1, 5)] {
+            slice.consume(skip);
+            reader.consume(skip);
+            assert_eq!(reader.peek(index), slice.peek(index));
+        }
+        assert!(reader.error().is_none());
+    }
+
+    #[test]
+    fn memory_wraps_within_the_segment() {
+        let mut memory = Memory::new();
+        memory.write_byte(physical_address(0x1000, 0xffff), 0x12);
+        memory.write_byte(physical_address(0x1000, 0x0000), 0x34);
+        let mut source = MemorySource {
+            memory: &memory,
+            segment: 0x1000,
+            offset: 0xffff,
+            remaining: 2,
+        };
+        assert_eq!(source.peek(0), Some(0x12));
+        assert_eq!(source.peek(1), Some(0x34));
+        assert_eq!(source.peek(2), None);
+        source.consume(1);
+        assert_eq!(source.peek(0), Some(0x34));
+    }
+}
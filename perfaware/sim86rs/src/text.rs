@@ -0,0 +1,72 @@
+use std::fmt::Write;
+
+use crate::instruction::{EffectiveAddress, Instruction, Operand, Width};
+
+/// Formats an effective address the way sim86_text.cpp does, e.g. `bp+si+4` or `+1000`.
+fn effective_address_text(address: &EffectiveAddress) -> String {
+    let mut text = String::new();
+    if let Some(base) = address.base {
+        let registers: Vec<String> = base
+            .registers()
+            .iter()
+            .map(|register| register.name().to_lowercase())
+            .collect();
+        text.push_str(&registers.join("+"));
+    }
+    match address.displacement {
+        Some(displacement) if displacement != 0 => {
+            write!(text, "{:+}", displacement).unwrap();
+        }
+        _ => {}
+    }
+    text
+}
+
+/// Renders `instruction` in the lowercase syntax of the reference decoder, as used in the
+/// `part1/*.txt` execution traces: `mov word [bp+si], si`, `jne $-6`, `mov bx, [+1000]`.
+pub fn reference_text(instruction: &Instruction) -> String {
+    let wide = instruction.width == Some(Width::Word);
+    let mut text = String::new();
+
+    if instruction.prefixes.lock {
+        text.push_str("lock ");
+    }
+    let mut suffix = "";
+    // The reference prints REPNE as plain `rep` too
+    if instruction.prefixes.rep.is_some() {
+        text.push_str("rep ");
+        suffix = if wide { "w" } else { "b" };
+    }
+    text.push_str(&instruction.mnemonic.name().to_lowercase());
+    text.push_str(suffix);
+
+    let mut separator = " ";
+    for operand in &instruction.operands {
+        text.push_str(separator);
+        separator = ", ";
+        match operand {
+            Operand::Register(register) => text.push_str(&register.to_string().to_lowercase()),
+            Operand::Memory(address) => {
+                if instruction.far {
+                    text.push_str("far ");
+                }
+                if !matches!(instruction.operands[0], Operand::Register(_)) {
+                    text.push_str(if wide { "word " } else { "byte " });
+                }
+                if let Some(segment) = instruction.prefixes.segment {
+                    write!(text, "{}:", segment.name().to_lowercase()).unwrap();
+                }
+                write!(text, "[{}]", effective_address_text(address)).unwrap();
+            }
+            Operand::Immediate(value) => write!(text, "{}", value).unwrap(),
+            Operand::Relative(displacement) => write!(
+                text,
+                "${:+}",
+                *displacement as i32 + instruction.length as i32
+            )
+            .unwrap(),
+            Operand::Far { segment, offset } => write!(text, "{}:{}", segment, offset).unwrap(),
+        }
+    }
+    text
+}
@@ -0,0 +1,223 @@
+use std::fmt::Write;
+use std::io;
+
+use crate::clocks::{self, Clocks, Cpu};
+use crate::error::TraceError;
+use crate::instruction::Instruction;
+use crate::registers::{IP, REGISTER_NAMES};
+use crate::simulator::{Simulator, Snapshot};
+use crate::text::reference_text;
+
+/// First line of a trace. The reference traces were recorded from a `test` directory on Windows.
+pub fn header(name: &str) -> String {
+    format!("--- test\\{} execution ---", name)
+}
+
+/// Banner ahead of a clocked trace, naming the CPU the clocks are for.
+pub fn clocks_banner(cpu: Cpu) -> String {
+    let stars = "*".repeat(14);
+    format!(
+        "{stars}\n**** {cpu} ****\n{stars}\n\n\
+         WARNING: Clocks reported by this utility are strictly from the 8086 manual.\n\
+         They will be inaccurate, both because the manual clocks are estimates, and because\n\
+         some of the entries in the manual look highly suspicious and are probably typos."
+    )
+}
+
+/// Every register and flag that changed, e.g. `cx:0x0->0x3 ip:0x0->0x3 `.
+fn changes(before: &Snapshot, after: &Snapshot, show_ip: bool) -> String {
+    let mut line = String::new();
+    for (index, name) in REGISTER_NAMES.iter().enumerate() {
+        let (old, new) = (before.registers.get(index), after.registers.get(index));
+        if old != new && (index != IP || show_ip) {
+            write!(line, "{}:{:#x}->{:#x} ", name.to_lowercase(), old, new).unwrap();
+        }
+    }
+    if before.flags != after.flags {
+        write!(line, "flags:{}->{} ", before.flags, after.flags).unwrap();
+    }
+    line
+}
+
+/// One executed instruction followed by every register and flag it changed, e.g.
+/// `mov cx, 3 ; cx:0x0->0x3 ip:0x0->0x3 `. Listings before 0048 were traced without IP.
+pub fn step_line(
+    instruction: &Instruction,
+    before: &Snapshot,
+    after: &Snapshot,
+    show_ip: bool,
+) -> String {
+    format!(
+        "{} ; {}",
+        reference_text(instruction),
+        changes(before, after, show_ip)
+    )
+}
+
+/// A step line with the clocks of the instruction and the running `total` in front of the
+/// changes, e.g. `mov cx, [bx] ; Clocks: +13 = 49 (8 + 5ea) | cx:0x3e8->0x0 `.
+pub fn clocked_step_line(
+    instruction: &Instruction,
+    clocks: &Clocks,
+    total: u32,
+    before: &Snapshot,
+    after: &Snapshot,
+    show_ip: bool,
+) -> String {
+    format!(
+        "{} ; Clocks: +{} = {}{} | {}",
+        reference_text(instruction),
+        clocks.total(),
+        total,
+        clocks,
+        changes(before, after, show_ip)
+    )
+}
+
+/// The closing register dump: every nonzero register, then the flags if any are set.
+pub fn final_registers(state: &Snapshot, show_ip: bool) -> String {
+    let mut text = String::from("Final registers:\n");
+    for (index, name) in REGISTER_NAMES.iter().enumerate() {
+        let value = state.registers.get(index);
+        if value != 0 && (index != IP || show_ip) {
+            writeln!(
+                text,
+                "{:>8}: 0x{:04x} ({})",
+                name.to_lowercase(),
+                value,
+                value
+            )
+            .unwrap();
+        }
+    }
+    if state.flags.0 != 0 {
+        writeln!(text, "{:>8}: {}", "flags", state.flags).unwrap();
+    }
+    text
+}
+
+/// What a trace prints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Options {
+    /// Estimate clocks for this CPU, with its banner ahead of the trace.
+    pub cpu: Option<Cpu>,
+    /// Print IP changes and the final IP.
+    pub show_ip: bool,
+    /// Print a line per executed instruction; otherwise only the final registers.
+    pub steps: bool,
+}
+
+/// Runs the program loaded into `simulator` to its end, writing the trace of `name` to `out`
+/// the way `exec` and `cycles` print it.
+pub fn run<W: io::Write + ?Sized>(
+    simulator: &mut Simulator,
+    name: &str,
+    options: Options,
+    out: &mut W,
+) -> Result<(), TraceError> {
+    if let Some(cpu) = options.cpu {
+        writeln!(out, "{}", clocks_banner(cpu))?;
+        writeln!(out)?;
+    }
+    writeln!(out, "{}", header(name))?;
+    let mut total = 0;
+    loop {
+        let before = simulator.snapshot();
+        match simulator.step() {
+            Some(Ok(instruction)) => {
+                let after = simulator.snapshot();
+                let clocks = options
+                    .cpu
+                    .and_then(|cpu| clocks::estimate(&instruction, cpu, &before, &after));
+                let line = match clocks {
+                    Some(clocks) => {
+                        total += clocks.total();
+                        clocked_step_line(
+                            &instruction,
+                            &clocks,
+                            total,
+                            &before,
+                            &after,
+                            options.show_ip,
+                        )
+                    }
+                    None => step_line(&instruction, &before, &after, options.show_ip),
+                };
+                if options.steps {
+                    writeln!(out, "{}", line)?;
+                }
+            }
+            Some(Err(exec_error)) => return Err(exec_error.into()),
+            None => break,
+        }
+    }
+    writeln!(out)?;
+    writeln!(
+        out,
+        "{}",
+        final_registers(&simulator.snapshot(), options.show_ip)
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode_instruction;
+    use crate::simulator::Simulator;
+
+    #[test]
+    fn byte_register_change_shows_whole_register() {
+        // mov ax, 0x2222; mov al, 0x11
+        let program = [0xb8, 0x22, 0x22, 0xb0, 0x11];
+        let mut simulator = Simulator::new();
+        simulator.load(&program);
+        simulator.step().unwrap().unwrap();
+        let before = simulator.snapshot();
+        let instruction = simulator.step().unwrap().unwrap();
+        assert_eq!(
+            step_line(&instruction, &before, &simulator.snapshot(), true),
+            "mov al, 17 ; ax:0x2222->0x2211 ip:0x3->0x5 "
+        );
+        assert_eq!(
+            step_line(&instruction, &before, &simulator.snapshot(), false),
+            "mov al, 17 ; ax:0x2222->0x2211 "
+        );
+    }
+
+    #[test]
+    fn memory_operands_use_reference_syntax() {
+        // mov word [bp + si], si; mov bx, [1000]
+        let program = [0x89, 0x32, 0x8b, 0x1e, 0xe8, 0x03];
+        let first = decode_instruction(&program, 0).unwrap().unwrap();
+        let second = decode_instruction(&program, 2).unwrap().unwrap();
+        assert_eq!(reference_text(&first), "mov word [bp+si], si");
+        assert_eq!(reference_text(&second), "mov bx, [+1000]");
+    }
+
+    #[test]
+    fn final_only_traces_skip_the_steps() {
+        // mov cx, 3; mov bx, cx
+        let program = [0xb9, 0x03, 0x00, 0x89, 0xcb];
+        let mut simulator = Simulator::new();
+        simulator.load(&program);
+        let options = Options {
+            cpu: None,
+            show_ip: false,
+            steps: false,
+        };
+        let mut out = Vec::new();
+        run(&mut simulator, "program", options, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            concat!(
+                "--- test\\program execution ---\n",
+                "\n",
+                "Final registers:\n",
+                "      bx: 0x0003 (3)\n",
+                "      cx: 0x0003 (3)\n",
+                "\n"
+            )
+        );
+    }
+}
@@ -0,0 +1,417 @@
+//! Differential tests against Casey's reference decoder, built from `sim86_lib.cpp` by the
+//! `contrib_rust` crate. Run with `cargo test --features reference`.
+//!
+//! Both decoders see the same bytes, and every instruction where the length, the operation
+//! (including prefixes) or the operands disagree is reported.
+#![cfg(feature = "reference")]
+
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use sim86_shared as reference;
+use sim86rs::{decode_instruction, Instruction, Mnemonic, Operand};
+
+/// Operands reduced to what both decoders can express. Numbers are compared as the 16-bit
+/// values the CPU would see, since the decoders disagree on signedness conventions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Shape {
+    Register(String),
+    Memory {
+        segment: Option<String>,
+        terms: Vec<String>,
+        displacement: u16,
+    },
+    Immediate(u16),
+    Relative(i16),
+    Far {
+        segment: u16,
+        offset: u16,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Decoded {
+    length: usize,
+    operation: String,
+    operands: Vec<Shape>,
+}
+
+fn from_reference(decoded: &reference::Instruction) -> Decoded {
+    let segment = decoded
+        .segment_override
+        .map(|segment| segment.name().into_owned());
+
+    let operands = decoded
+        .operands()
+        .map(|operand| match operand {
+            reference::Operand::Register(register) => Shape::Register(register.name().into_owned()),
+            reference::Operand::Memory(address) => match address.explicit_segment {
+                Some(explicit_segment) => Shape::Far {
+                    segment: explicit_segment as u16,
+                    offset: address.displacement as u16,
+                },
+                None => {
+                    let mut terms: Vec<String> = address
+                        .terms
+                        .iter()
+                        .flatten()
+                        .map(|term| term.register.name().into_owned())
+                        .collect();
+                    terms.sort();
+                    Shape::Memory {
+                        segment: segment.clone(),
+                        terms,
+                        displacement: address.displacement as u16,
+                    }
+                }
+            },
+            reference::Operand::Immediate(immediate) if immediate.relative => {
+                Shape::Relative(immediate.value as i16)
+            }
+            reference::Operand::Immediate(immediate) => Shape::Immediate(immediate.value as u16),
+        })
+        .collect();
+
+    let mut operation = String::new();
+    for (flag, prefix) in [
+        (reference::InstructionFlags::LOCK, "lock "),
+        (reference::InstructionFlags::REP, "rep "),
+        (reference::InstructionFlags::FAR, "far "),
+    ] {
+        if decoded.flags.contains(flag) {
+            operation.push_str(prefix);
+        }
+    }
+    operation.push_str(&decoded.operation.mnemonic());
+
+    Decoded {
+        length: decoded.size as usize,
+        operation,
+        operands,
+    }
+}
+
+fn from_sim86rs(decoded: &Instruction) -> Decoded {
+    let segment = decoded
+        .prefixes
+        .segment
+        .map(|segment| segment.name().to_lowercase());
+
+    let mut operands: Vec<Shape> = decoded
+        .operands
+        .iter()
+        .map(|operand| match operand {
+            Operand::Register(register) => Shape::Register(register.to_string().to_lowercase()),
+            Operand::Memory(address) => {
+                let mut terms: Vec<String> = address
+                    .base
+                    .map(|base| {
+                        base.registers()
+                            .iter()
+                            .map(|register| register.name().to_lowercase())
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                terms.sort();
+                Shape::Memory {
+                    segment: segment.clone(),
+                    terms,
+                    displacement: address.displacement.unwrap_or(0) as u16,
+                }
+            }
+            Operand::Immediate(value) => Shape::Immediate(*value as u16),
+            Operand::Relative(displacement) => Shape::Relative(*displacement),
+            Operand::Far { segment, offset } => Shape::Far {
+                segment: *segment,
+                offset: *offset,
+            },
+        })
+        .collect();
+
+    // The reference decodes 0x90 as the `xchg ax, ax` it really is
+    let mut mnemonic = decoded.mnemonic;
+    if mnemonic == Mnemonic::Nop {
+        mnemonic = Mnemonic::Xchg;
+        operands = vec![Shape::Register("ax".into()), Shape::Register("ax".into())];
+    }
+
+    let mut operation = String::new();
+    if decoded.prefixes.lock {
+        operation.push_str("lock ");
+    }
+    if decoded.prefixes.rep.is_some() {
+        operation.push_str("rep ");
+    }
+    if decoded.far {
+        operation.push_str("far ");
+    }
+    operation.push_str(&mnemonic.name().to_lowercase());
+
+    Decoded {
+        length: decoded.length,
+        operation,
+        operands,
+    }
+}
+
+/// Decodes the instruction at `offset` with both decoders. `None` means the decoder
+/// rejected the bytes.
+fn decode_both(buffer: &[u8], offset: usize) -> (Option<Decoded>, Option<Decoded>) {
+    let ours = match decode_instruction(buffer, offset) {
+        Some(Ok(decoded)) => Some(from_sim86rs(&decoded)),
+        _ => None,
+    };
+    // The reference pads short input with zeros, so only trust it within the buffer
+    let reference = reference::decode(&buffer[offset..])
+        .map(|decoded| from_reference(&decoded))
+        .filter(|decoded| decoded.length <= buffer.len() - offset);
+    (ours, reference)
+}
+
+fn describe(decoded: &Option<Decoded>) -> String {
+    match decoded {
+        Some(decoded) => format!("{:?}", decoded),
+        None => "<no instruction>".to_string(),
+    }
+}
+
+/// Prefix bytes both decoders fold into the instruction after them.
+const PREFIXES: &[u8] = &[0x26, 0x2e, 0x36, 0x3e, 0xf0, 0xf2, 0xf3];
+
+/// Encodings where sim86rs and the reference disagree on purpose: the undocumented forms the
+/// 8086 executes that the reference, following the manual, does not decode, and a few places
+/// where the reference itself is off.
+struct Divergence {
+    /// First byte after any prefixes.
+    opcodes: RangeInclusive<u8>,
+    /// MOD field values of the ModRM byte that diverge; empty when all of them do.
+    mod_field: &'static [u8],
+    /// REG field values of the ModRM byte that diverge; empty when all of them do.
+    reg: &'static [u8],
+    why: &'static str,
+}
+
+const ANY: &[u8] = &[];
+
+const DIVERGENCES: &[Divergence] = &[
+    Divergence {
+        opcodes: 0x82..=0x83,
+        mod_field: ANY,
+        reg: &[0b001, 0b100, 0b110],
+        why: "the reference only has AND, OR and XOR as 1000000w, but the 8086 honours S for them",
+    },
+    Divergence {
+        opcodes: 0x60..=0x6f,
+        mod_field: ANY,
+        reg: ANY,
+        why: "undocumented: the 8086 ignores bit 4, so these repeat the conditional jumps",
+    },
+    Divergence {
+        opcodes: 0xc0..=0xc1,
+        mod_field: ANY,
+        reg: ANY,
+        why: "undocumented: RET aliases of 0xC2 and 0xC3",
+    },
+    Divergence {
+        opcodes: 0xc8..=0xc9,
+        mod_field: ANY,
+        reg: ANY,
+        why: "undocumented: RETF aliases of 0xCA and 0xCB",
+    },
+    Divergence {
+        opcodes: 0xd6..=0xd6,
+        mod_field: ANY,
+        reg: ANY,
+        why: "undocumented: SALC",
+    },
+    Divergence {
+        opcodes: 0xf1..=0xf1,
+        mod_field: ANY,
+        reg: ANY,
+        why: "undocumented: LOCK alias",
+    },
+    Divergence {
+        opcodes: 0x8f..=0x8f,
+        mod_field: ANY,
+        reg: &[1, 2, 3, 4, 5, 6, 7],
+        why: "undocumented: POP ignores REG",
+    },
+    Divergence {
+        opcodes: 0xc6..=0xc7,
+        mod_field: ANY,
+        reg: &[1, 2, 3, 4, 5, 6, 7],
+        why: "undocumented: MOV immediate ignores REG",
+    },
+    Divergence {
+        opcodes: 0xff..=0xff,
+        mod_field: ANY,
+        reg: &[7],
+        why: "undocumented: REG 7 repeats PUSH",
+    },
+    Divergence {
+        opcodes: 0xf6..=0xf7,
+        mod_field: ANY,
+        reg: &[1],
+        why: "undocumented: REG 1 repeats TEST",
+    },
+    Divergence {
+        opcodes: 0x8c..=0x8c,
+        mod_field: ANY,
+        reg: &[4, 5, 6, 7],
+        why: "undocumented: MOV from a segment register ignores the third SR bit",
+    },
+    Divergence {
+        opcodes: 0x8e..=0x8e,
+        mod_field: ANY,
+        reg: &[4, 5, 6, 7],
+        why: "undocumented: MOV to a segment register ignores the third SR bit",
+    },
+    Divergence {
+        opcodes: 0xd8..=0xdf,
+        mod_field: ANY,
+        reg: ANY,
+        why: "the reference takes ESC's opcode fields for immediate data and reads a byte too many",
+    },
+    Divergence {
+        opcodes: 0x8d..=0x8d,
+        mod_field: &[0b11],
+        reg: ANY,
+        why: "LEA needs a memory operand; the reference accepts a register",
+    },
+    Divergence {
+        opcodes: 0xc4..=0xc5,
+        mod_field: &[0b11],
+        reg: ANY,
+        why: "LES and LDS need a memory operand; the reference accepts a register",
+    },
+];
+
+/// The index in `DIVERGENCES` of the intentional divergence `bytes` start with, if any.
+fn divergence(bytes: &[u8]) -> Option<usize> {
+    let start = bytes.iter().position(|byte| !PREFIXES.contains(byte))?;
+    let opcode = bytes[start];
+    let modrm = bytes.get(start + 1);
+    let matches = |values: &[u8], field: Option<u8>| {
+        values.is_empty() || field.is_some_and(|field| values.contains(&field))
+    };
+    DIVERGENCES.iter().position(|divergence| {
+        divergence.opcodes.contains(&opcode)
+            && matches(divergence.mod_field, modrm.map(|modrm| modrm >> 6))
+            && matches(divergence.reg, modrm.map(|modrm| (modrm >> 3) & 0b111))
+    })
+}
+
+/// Walks `buffer` with both decoders, collecting every disagreement. Stops at the first
+/// length mismatch since the two decoders are out of step after it.
+fn compare_stream(name: &str, buffer: &[u8], report: &mut Vec<String>) {
+    let mut offset = 0;
+    while offset < buffer.len() {
+        let (ours, reference) = decode_both(buffer, offset);
+        if ours != reference {
+            report.push(format!(
+                "{}@{:#x} {:02x?}:\n    sim86rs:   {}\n    reference: {}",
+                name,
+                offset,
+                &buffer[offset..buffer.len().min(offset + 6)],
+                describe(&ours),
+                describe(&reference)
+            ));
+        }
+        match (ours, reference) {
+            (Some(ours), Some(reference)) if ours.length == reference.length => {
+                offset += ours.length
+            }
+            _ => break,
+        }
+    }
+}
+
+/// Fails with the first `MAX_REPORTED` disagreements; a full report of a broken decoder is
+/// not readable.
+fn assert_no_disagreements(report: Vec<String>) {
+    const MAX_REPORTED: usize = 50;
+    assert!(
+        report.is_empty(),
+        "{} disagreements with the reference decoder:\n{}\n",
+        report.len(),
+        report[..report.len().min(MAX_REPORTED)].join("\n")
+    );
+}
+
+#[test]
+fn listings_agree_with_reference() {
+    let part1 = Path::new(env!("CARGO_MANIFEST_DIR")).join("../part1");
+    let mut listings: Vec<_> = fs::read_dir(part1)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            let name = path.file_name().unwrap().to_string_lossy();
+            name.starts_with("listing_") && path.extension().is_none()
+        })
+        .collect();
+    listings.sort();
+
+    let mut report = Vec::new();
+    for listing in listings {
+        let name = listing.file_name().unwrap().to_string_lossy().into_owned();
+        compare_stream(&name, &fs::read(&listing).unwrap(), &mut report);
+    }
+    assert_no_disagreements(report);
+}
+
+/// xorshift64, so runs are reproducible without pulling in a random number crate.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+/// Fuzzes the first instruction of random byte strings, skipping the encodings in
+/// `DIVERGENCES`. Ignored by default; run with `cargo test --features reference --
+/// --ignored`. `SIM86_FUZZ_ITERATIONS` and `SIM86_FUZZ_SEED` control the run.
+#[test]
+#[ignore]
+fn random_bytes_agree_with_reference() {
+    let iterations: usize = std::env::var("SIM86_FUZZ_ITERATIONS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(100_000);
+    let seed: u64 = std::env::var("SIM86_FUZZ_SEED")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(0x8086);
+    let mut rng = Rng(seed | 1);
+
+    let mut report = Vec::new();
+    let mut skipped = vec![0; DIVERGENCES.len()];
+    for _ in 0..iterations {
+        let bytes: Vec<u8> = (0..16).map(|_| rng.next() as u8).collect();
+        let (ours, reference) = decode_both(&bytes, 0);
+        if ours == reference {
+            continue;
+        }
+        if let Some(index) = divergence(&bytes) {
+            skipped[index] += 1;
+        } else {
+            report.push(format!(
+                "{:02x?}:\n    sim86rs:   {}\n    reference: {}",
+                &bytes[..6],
+                describe(&ours),
+                describe(&reference)
+            ));
+        }
+    }
+    for (divergence, count) in DIVERGENCES.iter().zip(skipped) {
+        eprintln!(
+            "skipped {} intentional divergences: {}",
+            count, divergence.why
+        );
+    }
+    assert_no_disagreements(report);
+}
@@ -0,0 +1,545 @@
+//! Golden-file tests over every listing in `perfaware/part1`.
+//!
+//! Each binary is disassembled and compared against its `.asm` source after normalizing case,
+//! whitespace, comments, number formats and label names. Listings with a `.txt` trace are also
+//! executed and compared byte for byte. New listings are picked up automatically.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use sim86rs::clocks::Cpu;
+use sim86rs::labels::Labels;
+use sim86rs::simulator::Simulator;
+use sim86rs::{decode, trace, Operand};
+
+/// Listings whose disassembly is known not to match yet.
+const KNOWN_DISASSEMBLY_FAILURES: &[&str] = &[];
+
+/// Listings whose trace is known not to match yet.
+const KNOWN_TRACE_FAILURES: &[&str] = &[];
+
+const REGISTERS: &[&str] = &[
+    "al", "cl", "dl", "bl", "ah", "ch", "dh", "bh", "ax", "cx", "dx", "bx", "sp", "bp", "si", "di",
+    "es", "cs", "ss", "ds",
+];
+
+fn part1_directory() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../part1")
+}
+
+/// Every listing binary, i.e. every `listing_*` file without an extension.
+fn listings() -> Vec<PathBuf> {
+    let mut listings: Vec<PathBuf> = fs::read_dir(part1_directory())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            let name = path.file_name().unwrap().to_string_lossy();
+            name.starts_with("listing_") && path.extension().is_none()
+        })
+        .collect();
+    listings.sort();
+    listings
+}
+
+fn name_of(listing: &Path) -> String {
+    listing.file_name().unwrap().to_string_lossy().into_owned()
+}
+
+fn with_extension(listing: &Path, extension: &str) -> PathBuf {
+    let mut path = listing.as_os_str().to_owned();
+    path.push(".");
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+/// Evaluates a constant expression made of numbers joined by `+`, `-` and `*`.
+fn evaluate(expression: &str) -> Option<i64> {
+    let mut total = 0;
+    for (sign, term) in signed_terms(expression) {
+        let mut product = 1;
+        for factor in term.split('*') {
+            product *= parse_number(factor)?;
+        }
+        total += sign * product;
+    }
+    Some(total)
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    if let Some(hex) = text.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(hex) = text.strip_suffix('h') {
+        i64::from_str_radix(hex, 16).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+/// Splits `bp+si-0x3a` into `[(1, "bp"), (1, "si"), (-1, "0x3a")]`.
+fn signed_terms(expression: &str) -> Vec<(i64, String)> {
+    let mut terms = Vec::new();
+    let mut sign = 1;
+    let mut term = String::new();
+    for c in expression.chars() {
+        if c == '+' || c == '-' {
+            if !term.is_empty() {
+                terms.push((sign, std::mem::take(&mut term)));
+            }
+            sign = if c == '-' { -1 } else { 1 };
+        } else {
+            term.push(c);
+        }
+    }
+    if !term.is_empty() {
+        terms.push((sign, term));
+    }
+    terms
+}
+
+/// An operand with its numbers pulled out, so `[bx+si-4]` becomes `[bx+si+#]` and `[-4]`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct NormalizedOperand {
+    shape: String,
+    numbers: Vec<i64>,
+}
+
+#[derive(Debug, Clone)]
+struct NormalizedLine {
+    mnemonic: String,
+    operands: Vec<NormalizedOperand>,
+}
+
+fn canonical_mnemonic(mnemonic: &str) -> &str {
+    match mnemonic {
+        "jz" => "je",
+        "jnz" => "jne",
+        "jnge" => "jl",
+        "jge" => "jnl",
+        "jng" => "jle",
+        "jnle" => "jg",
+        "jc" | "jnae" => "jb",
+        "jnc" | "jae" => "jnb",
+        "jna" => "jbe",
+        "jnbe" => "ja",
+        "jpe" => "jp",
+        "jpo" => "jnp",
+        "loope" => "loopz",
+        "loopne" => "loopnz",
+        "sal" => "shl",
+        other => other,
+    }
+}
+
+fn is_branch(mnemonic: &str) -> bool {
+    mnemonic.starts_with('j') || mnemonic.starts_with("loop") || mnemonic == "call"
+}
+
+/// Normalizes one operand. `target` resolves labels and `$+N` jumps to absolute addresses.
+fn normalize_operand(
+    mnemonic: &str,
+    operand: &str,
+    target: &dyn Fn(&str) -> Option<i64>,
+) -> NormalizedOperand {
+    let operand: String = operand
+        .split_whitespace()
+        .filter(|word| *word != "byte" && *word != "word")
+        .collect();
+
+    if is_branch(mnemonic) {
+        if let Some(address) = target(&operand) {
+            return NormalizedOperand {
+                shape: "@".to_string(),
+                numbers: vec![address],
+            };
+        }
+    }
+
+    if let (Some(open), Some(close)) = (operand.find('['), operand.rfind(']')) {
+        let mut registers = Vec::new();
+        let mut displacement = 0;
+        for (sign, term) in signed_terms(&operand[open + 1..close]) {
+            if REGISTERS.contains(&term.as_str()) {
+                registers.push(term);
+            } else {
+                displacement += sign * evaluate(&term).unwrap_or(0);
+            }
+        }
+        registers.sort();
+        let mut shape = format!("{}[{}", &operand[..open], registers.join("+"));
+        let mut numbers = Vec::new();
+        if displacement != 0 || registers.is_empty() {
+            shape.push_str("+#");
+            numbers.push(displacement);
+        }
+        shape.push(']');
+        return NormalizedOperand { shape, numbers };
+    }
+
+    match evaluate(&operand) {
+        Some(value) if !REGISTERS.contains(&operand.as_str()) => NormalizedOperand {
+            shape: "#".to_string(),
+            numbers: vec![value],
+        },
+        _ => {
+            // Far pointers such as `123:456`
+            let parts: Vec<Option<i64>> = operand.split(':').map(evaluate).collect();
+            if parts.len() == 2 && parts.iter().all(Option::is_some) {
+                NormalizedOperand {
+                    shape: "#:#".to_string(),
+                    numbers: parts.into_iter().flatten().collect(),
+                }
+            } else {
+                NormalizedOperand {
+                    shape: operand,
+                    numbers: Vec::new(),
+                }
+            }
+        }
+    }
+}
+
+fn normalize_line(line: &str, target: &dyn Fn(&str) -> Option<i64>) -> NormalizedLine {
+    let line = line.trim().to_lowercase();
+    let (mnemonic, rest) = match line.split_once(char::is_whitespace) {
+        Some((mnemonic, rest)) => (mnemonic.to_string(), rest.trim().to_string()),
+        None => (line.clone(), String::new()),
+    };
+    let (mnemonic, rest) = match mnemonic.as_str() {
+        // `lock` and `rep` stay part of the mnemonic
+        "lock" | "rep" | "repe" | "repz" | "repne" | "repnz" => {
+            let (next, rest) = rest.split_once(char::is_whitespace).unwrap_or((&rest, ""));
+            (format!("{} {}", mnemonic, next), rest.trim().to_string())
+        }
+        _ => (mnemonic, rest),
+    };
+    let mut mnemonic = canonical_mnemonic(&mnemonic).to_string();
+
+    let mut operands: Vec<NormalizedOperand> = if rest.is_empty() {
+        Vec::new()
+    } else {
+        rest.split(',')
+            .map(|operand| normalize_operand(&mnemonic, operand, target))
+            .collect()
+    };
+
+    if mnemonic == "nop" {
+        mnemonic = "xchg".to_string();
+        operands = ["ax", "ax"]
+            .iter()
+            .map(|register| normalize_operand("xchg", register, target))
+            .collect();
+    }
+    // Operand order is not significant for these, and assemblers disagree on it
+    if mnemonic.ends_with("xchg") || mnemonic == "test" {
+        operands.sort();
+    }
+
+    NormalizedLine { mnemonic, operands }
+}
+
+/// Numbers agree if they are the same 16-bit value, or the same byte when both fit in one.
+fn numbers_match(expected: i64, actual: i64) -> bool {
+    let byte_range = -128..=255;
+    (expected - actual).rem_euclid(0x10000) == 0
+        || (byte_range.contains(&expected)
+            && byte_range.contains(&actual)
+            && (expected - actual).rem_euclid(0x100) == 0)
+}
+
+fn lines_match(expected: &NormalizedLine, actual: &NormalizedLine) -> bool {
+    expected.mnemonic == actual.mnemonic
+        && expected.operands.len() == actual.operands.len()
+        && expected
+            .operands
+            .iter()
+            .zip(&actual.operands)
+            .all(|(expected, actual)| {
+                expected.shape == actual.shape
+                    && expected.numbers.len() == actual.numbers.len()
+                    && expected
+                        .numbers
+                        .iter()
+                        .zip(&actual.numbers)
+                        .all(|(e, a)| numbers_match(*e, *a))
+            })
+}
+
+fn check_disassembly(listing: &Path) -> Result<(), String> {
+    compare_disassembly(listing, false)
+}
+
+fn check_labelled_disassembly(listing: &Path) -> Result<(), String> {
+    compare_disassembly(listing, true)
+}
+
+/// Compares the disassembly of one listing against its source, returning a description of
+/// the first mismatch. With `labelled`, branches are printed as `--labels` prints them.
+fn compare_disassembly(listing: &Path, labelled: bool) -> Result<(), String> {
+    let binary = fs::read(listing).unwrap();
+    let source = fs::read_to_string(with_extension(listing, "asm")).unwrap();
+    let instructions = decode(&binary).map_err(|error| error.to_string())?;
+    let our_labels = if labelled {
+        Labels::collect(&instructions)
+    } else {
+        Labels::default()
+    };
+    let our_label_addresses: HashMap<&str, i64> = instructions
+        .iter()
+        .filter_map(|instruction| {
+            let label = our_labels.at(instruction.address)?;
+            Some((label, instruction.address as i64))
+        })
+        .collect();
+
+    // Source lines without comments, blank lines and directives; labels are recorded with
+    // the index of the instruction they point at.
+    let mut source_lines = Vec::new();
+    let mut labels = HashMap::new();
+    for line in source.lines() {
+        let line = line.split(';').next().unwrap().trim().to_lowercase();
+        if line.is_empty() || line.starts_with("bits") {
+            continue;
+        }
+        if let Some(label) = line.strip_suffix(':') {
+            labels.insert(label.to_string(), source_lines.len());
+            continue;
+        }
+        source_lines.push(line);
+    }
+
+    let address_of = |index: usize| -> i64 {
+        instructions
+            .get(index)
+            .map_or(binary.len(), |instruction| instruction.address) as i64
+    };
+    let source_target = |operand: &str| -> Option<i64> {
+        match labels.get(operand) {
+            Some(index) => Some(address_of(*index)),
+            None if !operand.contains('[') && !operand.contains(':') => {
+                evaluate(operand).filter(|_| !REGISTERS.contains(&operand))
+            }
+            None => None,
+        }
+    };
+
+    for (index, expected_text) in source_lines.iter().enumerate() {
+        let instruction = instructions
+            .get(index)
+            .ok_or_else(|| format!("missing instruction for `{}`", expected_text))?;
+        let actual_text = our_labels.render(instruction);
+        let relative = instruction
+            .operands
+            .iter()
+            .any(|operand| matches!(operand, Operand::Relative(_)));
+        let address = instruction.address as i64;
+        let actual_target = |operand: &str| -> Option<i64> {
+            if let Some(address) = our_label_addresses.get(operand) {
+                return Some(*address);
+            }
+            let offset = operand.strip_prefix('$').and_then(evaluate)?;
+            relative.then_some(address + offset)
+        };
+        let expected = normalize_line(expected_text, &source_target);
+        let actual = normalize_line(&actual_text, &actual_target);
+        if !lines_match(&expected, &actual) {
+            return Err(format!(
+                "instruction {} at {:#x}: expected `{}`, got `{}`",
+                index, instruction.address, expected_text, actual_text
+            ));
+        }
+    }
+    if instructions.len() > source_lines.len() {
+        return Err(format!(
+            "{} extra instructions decoded",
+            instructions.len() - source_lines.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Describes where two binaries first disagree.
+fn compare_bytes(expected: &[u8], actual: &[u8]) -> Result<(), String> {
+    match expected.iter().zip(actual).position(|(e, a)| e != a) {
+        Some(offset) => Err(format!(
+            "byte {:#x}: expected {:02x?}, got {:02x?}",
+            offset,
+            &expected[offset..expected.len().min(offset + 6)],
+            &actual[offset..actual.len().min(offset + 6)]
+        )),
+        None if expected.len() != actual.len() => Err(format!(
+            "expected {} bytes, got {}",
+            expected.len(),
+            actual.len()
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Assembles the `.asm` source and compares it with the binary NASM made from it.
+fn check_assembly(listing: &Path) -> Result<(), String> {
+    let binary = fs::read(listing).unwrap();
+    let source = fs::read_to_string(with_extension(listing, "asm")).unwrap();
+    let assembled = sim86rs::assemble(&source).map_err(|error| error.to_string())?;
+    compare_bytes(&binary, &assembled)
+}
+
+/// Disassembles the binary, with and without labels, and assembles the text back.
+fn check_round_trip(listing: &Path) -> Result<(), String> {
+    let binary = fs::read(listing).unwrap();
+    let instructions = decode(&binary).map_err(|error| error.to_string())?;
+    for labels in [Labels::default(), Labels::collect(&instructions)] {
+        let mut text = String::from("bits 16\n");
+        for instruction in &instructions {
+            if let Some(label) = labels.at(instruction.address) {
+                text.push_str(&format!("{}:\n", label));
+            }
+            text.push_str(&labels.render(instruction));
+            text.push('\n');
+        }
+        let assembled = sim86rs::assemble(&text).map_err(|error| error.to_string())?;
+        compare_bytes(&binary, &assembled)?;
+    }
+    Ok(())
+}
+
+/// Runs a listing and renders its full trace the way `exec` prints it, or the way `cycles`
+/// prints it for one CPU.
+fn run_trace(listing: &Path, show_ip: bool, cpu: Option<Cpu>) -> Result<String, String> {
+    let binary = fs::read(listing).unwrap();
+    let mut simulator = Simulator::new();
+    simulator.load(&binary);
+    let options = trace::Options {
+        cpu,
+        show_ip,
+        steps: true,
+    };
+    let mut text = Vec::new();
+    trace::run(&mut simulator, &name_of(listing), options, &mut text)
+        .map_err(|error| error.to_string())?;
+    Ok(String::from_utf8(text).unwrap())
+}
+
+fn compare_trace(expected: &str, actual: &str) -> Result<(), String> {
+    match expected
+        .lines()
+        .zip(actual.lines())
+        .enumerate()
+        .find(|(_, (expected, actual))| expected != actual)
+    {
+        Some((line, (expected, actual))) => Err(format!(
+            "line {}: expected `{}`, got `{}`",
+            line + 1,
+            expected,
+            actual
+        )),
+        None if expected != actual => Err("trace length differs".to_string()),
+        None => Ok(()),
+    }
+}
+
+/// Drops trailing whitespace from every line and trailing blank lines from the end.
+fn trim_lines(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().map(str::trim_end).collect();
+    lines.join("\n").trim_end().to_string()
+}
+
+fn check_trace(listing: &Path) -> Result<(), String> {
+    let expected = fs::read_to_string(with_extension(listing, "txt"))
+        .unwrap()
+        .replace("\r\n", "\n");
+    // Listings before 0048 were traced before IP was tracked
+    let show_ip = expected.contains("ip:");
+    if !expected.starts_with('*') {
+        return compare_trace(&expected, &run_trace(listing, show_ip, None)?);
+    }
+
+    // Clocked listings hold one trace per CPU, each behind its banner. The 8088 halves were
+    // recorded without the trailing space on each line, so whitespace at line ends and the
+    // blank lines between sections are not compared.
+    let banner = "**************\n**** ";
+    let starts: Vec<usize> = expected
+        .match_indices(banner)
+        .map(|(index, _)| index)
+        .collect();
+    for (index, start) in starts.iter().enumerate() {
+        let section = &expected[*start..starts.get(index + 1).copied().unwrap_or(expected.len())];
+        let cpu = Cpu::ALL
+            .into_iter()
+            .find(|cpu| section[banner.len()..].starts_with(&cpu.to_string()))
+            .ok_or("unknown CPU in banner")?;
+        let actual = run_trace(listing, show_ip, Some(cpu))?;
+        compare_trace(&trim_lines(section), &trim_lines(&actual))
+            .map_err(|error| format!("{}: {}", cpu, error))?;
+    }
+    Ok(())
+}
+
+/// Runs `check` over `listings`, failing on unexpected failures and on known failures that
+/// now pass (so the known-failure list shrinks as the decoder improves).
+fn check_all(
+    listings: &[PathBuf],
+    known_failures: &[&str],
+    check: fn(&Path) -> Result<(), String>,
+) {
+    let mut problems = Vec::new();
+    for listing in listings {
+        let name = name_of(listing);
+        let known = known_failures.contains(&name.as_str());
+        match (check(listing), known) {
+            (Err(error), false) => problems.push(format!("{}: {}", name, error)),
+            (Ok(()), true) => {
+                problems.push(format!("{}: passes but is listed as known failure", name))
+            }
+            _ => {}
+        }
+    }
+    assert!(problems.is_empty(), "\n{}\n", problems.join("\n"));
+}
+
+#[test]
+fn disassembly_matches_source() {
+    let listings: Vec<PathBuf> = listings()
+        .into_iter()
+        .filter(|listing| with_extension(listing, "asm").exists())
+        .collect();
+    assert!(!listings.is_empty());
+    check_all(&listings, KNOWN_DISASSEMBLY_FAILURES, check_disassembly);
+}
+
+#[test]
+fn labelled_disassembly_matches_source() {
+    let listings: Vec<PathBuf> = listings()
+        .into_iter()
+        .filter(|listing| with_extension(listing, "asm").exists())
+        .collect();
+    check_all(
+        &listings,
+        KNOWN_DISASSEMBLY_FAILURES,
+        check_labelled_disassembly,
+    );
+}
+
+#[test]
+fn execution_matches_reference_trace() {
+    let listings: Vec<PathBuf> = listings()
+        .into_iter()
+        .filter(|listing| with_extension(listing, "txt").exists())
+        .collect();
+    assert!(!listings.is_empty());
+    check_all(&listings, KNOWN_TRACE_FAILURES, check_trace);
+}
+
+#[test]
+fn assembly_matches_nasm() {
+    let listings: Vec<PathBuf> = listings()
+        .into_iter()
+        .filter(|listing| with_extension(listing, "asm").exists())
+        .collect();
+    check_all(&listings, &[], check_assembly);
+}
+
+#[test]
+fn disassembly_reassembles_to_the_same_bytes() {
+    check_all(&listings(), &[], check_round_trip);
+}